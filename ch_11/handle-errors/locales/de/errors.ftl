@@ -0,0 +1,56 @@
+# Details of the problem documents, one message per error code.
+# The codes are part of the API and stay in English in every locale.
+
+not_found = Route nicht gefunden
+invalid_body = Ungültiger Request-Body ({ $reason })
+cors_forbidden = CORS-Anfrage abgelehnt ({ $reason })
+internal_error = Interner Serverfehler
+
+invalid_parameter = Parameter kann nicht gelesen werden: { $reason }
+missing_parameters = Parameter fehlt
+wrong_credentials = E-Mail-Adresse oder Passwort falsch
+invalid_token = Token kann nicht entschlüsselt werden
+unauthorized = Keine Berechtigung, die Ressource zu ändern
+password_verification_failed = Passwort kann nicht geprüft werden
+migration_failed = Daten können nicht migriert werden
+upstream_request_failed = { internal_error }
+upstream_client_error = { internal_error }
+upstream_server_error = { internal_error }
+email_not_verified = Bitte bestätige zuerst deine E-Mail-Adresse
+too_many_requests = Zu viele Anfragen, bitte versuche es später erneut
+email_already_in_use = Die E-Mail-Adresse wird bereits verwendet
+mail_delivery_failed = { internal_error }
+io_error = { internal_error }
+configuration_error = { internal_error }
+validation_failed = Ungültige Eingabe
+oidc_login_failed = Anmeldung beim Identitätsanbieter fehlgeschlagen
+two_factor_already_enabled = Zwei-Faktor-Authentifizierung ist bereits aktiviert
+two_factor_not_enabled = Zwei-Faktor-Authentifizierung ist nicht eingerichtet
+invalid_two_factor_code = Ungültiger Bestätigungscode
+moderation_unavailable = Inhaltsmoderation nicht verfügbar, bitte versuche es später erneut
+reauthentication_required = Bitte melde dich zur Bestätigung erneut an
+
+resource_not_found = Ressource nicht gefunden
+resource_already_exists = Ressource existiert bereits
+referenced_resource_not_found = Referenzierte Ressource nicht gefunden
+resource_still_referenced = Ressource wird noch von anderen Ressourcen verwendet
+transaction_conflict = Gleichzeitige Änderung, bitte versuche es erneut
+database_unavailable = Datenbank vorübergehend nicht erreichbar, bitte versuche es später erneut
+invalid_data = Daten können nicht gespeichert werden
+database_error = { internal_error }
+
+## Messages of invalid fields, shown after the field name
+
+field_invalid = ist ungültig
+field_empty = darf nicht leer sein
+field_too_short = muss mindestens { $min } Zeichen lang sein
+field_too_long = darf höchstens { $max } Zeichen lang sein
+field_invalid_email = ist keine gültige E-Mail-Adresse
+field_breached_password = ist in einem Datenleck aufgetaucht, bitte wähle ein anderes
+field_taken = ist bereits vergeben
+field_too_many_tags = darf höchstens { $max } Tags enthalten
+field_tag_length = darf nur Tags mit 1 bis { $max } Zeichen enthalten
+field_tag_format = „{ $tag }“ darf nur Buchstaben, Ziffern, '-', '.', '+' und '#' enthalten
+field_no_scopes = muss mindestens einen Scope enthalten
+field_not_in_future = muss in der Zukunft liegen
+field_http_url = muss eine http- oder https-URL sein
@@ -0,0 +1,56 @@
+# Details of the problem documents, one message per error code.
+# The codes are part of the API and stay in English in every locale.
+
+not_found = Route not found
+invalid_body = { $reason }
+cors_forbidden = { $reason }
+internal_error = Internal Server Error
+
+invalid_parameter = Cannot parse parameter: { $reason }
+missing_parameters = Missing parameter
+wrong_credentials = Wrong E-Mail/Password combination
+invalid_token = Cannot decrypt error
+unauthorized = No permission to change underlying resource
+password_verification_failed = Cannot verifiy password
+migration_failed = Cannot migrate data
+upstream_request_failed = { internal_error }
+upstream_client_error = { internal_error }
+upstream_server_error = { internal_error }
+email_not_verified = Please verify your email address first
+too_many_requests = Too many requests, please try again later
+email_already_in_use = Email address is already in use
+mail_delivery_failed = { internal_error }
+io_error = { internal_error }
+configuration_error = { internal_error }
+validation_failed = Invalid input
+oidc_login_failed = Cannot sign in with the identity provider
+two_factor_already_enabled = Two-factor authentication is already enabled
+two_factor_not_enabled = Two-factor authentication is not set up
+invalid_two_factor_code = Invalid authentication code
+moderation_unavailable = Content moderation is unavailable, please try again later
+reauthentication_required = Please sign in again to confirm
+
+resource_not_found = Resource not found
+resource_already_exists = Resource already exists
+referenced_resource_not_found = Referenced resource not found
+resource_still_referenced = Resource is still referenced by other resources
+transaction_conflict = Conflicting concurrent update, please try again
+database_unavailable = Database temporarily unavailable, please try again later
+invalid_data = Cannot update data
+database_error = { internal_error }
+
+## Messages of invalid fields, shown after the field name
+
+field_invalid = is invalid
+field_empty = must not be empty
+field_too_short = must be at least { $min } characters long
+field_too_long = must not be longer than { $max } characters
+field_invalid_email = is not a valid email address
+field_breached_password = appeared in a data breach, please choose another one
+field_taken = is already taken
+field_too_many_tags = must not contain more than { $max } tags
+field_tag_length = must only contain tags of 1 to { $max } characters
+field_tag_format = "{ $tag }" may only contain letters, digits, '-', '.', '+' and '#'
+field_no_scopes = must contain at least one scope
+field_not_in_future = must be in the future
+field_http_url = must be an http or https URL
@@ -1,14 +1,23 @@
+use std::convert::Infallible;
 use warp::{
     filters::{body::BodyDeserializeError, cors::CorsForbidden},
-    http::StatusCode,
+    http::{
+        header::{ACCEPT, ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_TYPE, RETRY_AFTER},
+        HeaderMap, HeaderValue, StatusCode,
+    },
     reject::Reject,
-    Rejection, Reply,
+    reply::Response,
+    Filter, Rejection, Reply,
 };
 use tracing::{event, Level, instrument};
 use argon2::Error as ArgonError;
 use reqwest::Error as ReqwestError;
 use reqwest_middleware::Error as MiddlewareReqwestError;
+use serde::Serialize;
 
+mod messages;
+
+pub use messages::Locale;
 
 #[derive(Debug)]
 pub enum Error {
@@ -23,7 +32,90 @@ pub enum Error {
     ReqwestAPIError(ReqwestError),
     MiddlewareReqwestAPIError(MiddlewareReqwestError),
     ClientError(APILayerError),
-    ServerError(APILayerError)
+    ServerError(APILayerError),
+    EmailNotVerified,
+    /// Carries the number of seconds after which the client may retry
+    TooManyRequests(u64),
+    EmailAlreadyInUse,
+    MailError(Box<dyn std::error::Error + Send + Sync>),
+    IoError(std::io::Error),
+    ConfigError(String),
+    /// Lists every invalid field of the request, not just the first one
+    ValidationError(Vec<FieldError>),
+    /// The identity provider refused the login or sent an unusable response
+    OidcError(String),
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotEnabled,
+    /// Neither a current TOTP code nor an unused recovery code
+    InvalidTwoFactorCode,
+    /// The moderation service failed and content is rejected until it recovers.
+    /// Carries the number of seconds after which the client may retry.
+    ModerationUnavailable(u64),
+    /// Accounts without a password confirm sensitive changes by signing in again
+    ReauthenticationRequired,
+}
+
+/// Message of fields violating a rule without a message of its own
+const FIELD_INVALID: &str = "field_invalid";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+    /// Catalog entry of the message, `None` for messages which are never translated
+    #[serde(skip)]
+    message_id: Option<String>,
+    #[serde(skip)]
+    args: Vec<(String, String)>,
+}
+
+/// Errors are equal if the client sees the same, regardless of where the message came from
+impl PartialEq for FieldError {
+    fn eq(&self, other: &Self) -> bool {
+        self.field == other.field && self.message == other.message
+    }
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.into(),
+            message_id: None,
+            args: Vec::new(),
+        }
+    }
+
+    /// Message from the catalogs, in English until the response is localized.
+    /// Ids missing from the catalogs get the generic `field_invalid` message.
+    pub fn localized(field: &str, message_id: &str, args: &[(&str, String)]) -> Self {
+        let args = messages::message_args(args);
+        let (message_id, message) = match Locale::DEFAULT.message(message_id, &args) {
+            Some(message) => (message_id, message),
+            None => (
+                FIELD_INVALID,
+                Locale::DEFAULT
+                    .message(FIELD_INVALID, &[])
+                    .unwrap_or_default(),
+            ),
+        };
+        FieldError {
+            field: field.to_string(),
+            message,
+            message_id: Some(message_id.to_string()),
+            args,
+        }
+    }
+
+    pub fn localize(&mut self, locale: Locale) {
+        if let Some(message) = self
+            .message_id
+            .as_deref()
+            .and_then(|id| locale.message(id, &self.args))
+        {
+            self.message = message;
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +130,40 @@ impl std::fmt::Display for APILayerError {
     }
 }
 
+impl Error {
+    /// Machine-readable code sent with the problem document. Clients branch
+    /// on it, so existing codes must never change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ParseError(_) => "invalid_parameter",
+            Error::MissingParameters => "missing_parameters",
+            Error::WrongPassword => "wrong_credentials",
+            Error::CannotDecryptToken => "invalid_token",
+            Error::Unauthorized => "unauthorized",
+            Error::ArgonLibraryError(_) => "password_verification_failed",
+            Error::DatabaseQueryError(e) => DatabaseFailure::of(e).code(),
+            Error::MigrationError(_) => "migration_failed",
+            Error::ReqwestAPIError(_) => "upstream_request_failed",
+            Error::MiddlewareReqwestAPIError(_) => "upstream_request_failed",
+            Error::ClientError(_) => "upstream_client_error",
+            Error::ServerError(_) => "upstream_server_error",
+            Error::EmailNotVerified => "email_not_verified",
+            Error::TooManyRequests(_) => "too_many_requests",
+            Error::EmailAlreadyInUse => "email_already_in_use",
+            Error::MailError(_) => "mail_delivery_failed",
+            Error::IoError(_) => "io_error",
+            Error::ConfigError(_) => "configuration_error",
+            Error::ValidationError(_) => "validation_failed",
+            Error::OidcError(_) => "oidc_login_failed",
+            Error::TwoFactorAlreadyEnabled => "two_factor_already_enabled",
+            Error::TwoFactorNotEnabled => "two_factor_not_enabled",
+            Error::InvalidTwoFactorCode => "invalid_two_factor_code",
+            Error::ModerationUnavailable(_) => "moderation_unavailable",
+            Error::ReauthenticationRequired => "reauthentication_required",
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match &*self {
@@ -53,6 +179,27 @@ impl std::fmt::Display for Error {
             Error::MiddlewareReqwestAPIError(err) => write!(f, "External API error: {}", err),
             Error::ClientError(err) => write!(f, "External Client error: {}", err),
             Error::ServerError(err) => write!(f, "External Server error: {}", err),
+            Error::EmailNotVerified => write!(f, "Email address is not verified"),
+            Error::TooManyRequests(_) => write!(f, "Too many requests"),
+            Error::EmailAlreadyInUse => write!(f, "Email address is already in use"),
+            Error::MailError(err) => write!(f, "Cannot send email: {}", err),
+            Error::IoError(err) => write!(f, "Cannot read file: {}", err),
+            Error::ConfigError(err) => write!(f, "Invalid configuration: {}", err),
+            Error::ValidationError(errors) => {
+                let fields = errors
+                    .iter()
+                    .map(|e| format!("{} {}", e.field, e.message))
+                    .collect::<Vec<_>>();
+                write!(f, "Invalid input: {}", fields.join(", "))
+            }
+            Error::OidcError(err) => write!(f, "OpenID Connect login failed: {}", err),
+            Error::TwoFactorAlreadyEnabled => {
+                write!(f, "Two-factor authentication is already enabled")
+            }
+            Error::TwoFactorNotEnabled => write!(f, "Two-factor authentication is not set up"),
+            Error::InvalidTwoFactorCode => write!(f, "Invalid authentication code"),
+            Error::ModerationUnavailable(_) => write!(f, "Content moderation is unavailable"),
+            Error::ReauthenticationRequired => write!(f, "Sign in again to confirm"),
         }
     }
 }
@@ -60,93 +207,608 @@ impl std::fmt::Display for Error {
 impl Reject for Error {}
 impl Reject for APILayerError {}
 
-const DUPLICATE_KEY: u32 = 23505;
+/// SQLSTATE codes of PostgreSQL (Appendix A of its documentation)
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const SERIALIZATION_FAILURE: &str = "40001";
+const DEADLOCK_DETECTED: &str = "40P01";
 
-#[instrument]
-pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
-    if let Some(crate::Error::DatabaseQueryError(e)) = r.find() {
-        event!(Level::ERROR, "Database query error");
-
-        match e {
-            sqlx::Error::Database(err) => {
-                if err.code().unwrap().parse::<u32>().unwrap() == DUPLICATE_KEY {
-                    Ok(warp::reply::with_status(
-                        "Account already exsists".to_string(),
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
-                } else {
-                    Ok(warp::reply::with_status(
-                        "Cannot update data".to_string(),
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
+/// Input field behind a unique constraint, so clients can show the conflict next to it
+const UNIQUE_CONSTRAINT_FIELDS: &[(&str, &str)] = &[
+    ("accounts_pkey", "email"),
+    ("accounts_email_lower_key", "email"),
+];
+
+/// Seconds after which a query failing for lack of resources is worth retrying
+const CONTENTION_RETRY_AFTER: u64 = 1;
+const UNAVAILABLE_RETRY_AFTER: u64 = 5;
+
+/// How a failed query is reported to the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DatabaseFailure {
+    NotFound,
+    Duplicate(Option<&'static str>),
+    /// The row refers to a row which does not exist
+    MissingReference,
+    /// The row is still referred to by other rows
+    StillReferenced,
+    /// Serialization failure or deadlock, the transaction can simply be retried
+    Contention,
+    /// No connection became available in time
+    Unavailable,
+    InvalidData,
+    Internal,
+}
+
+impl DatabaseFailure {
+    fn of(error: &sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => DatabaseFailure::NotFound,
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => DatabaseFailure::Unavailable,
+            sqlx::Error::Database(err) => match err.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => DatabaseFailure::Duplicate(
+                    err.constraint().and_then(|constraint| {
+                        UNIQUE_CONSTRAINT_FIELDS
+                            .iter()
+                            .find(|(name, _)| *name == constraint)
+                            .map(|(_, field)| *field)
+                    }),
+                ),
+                // Inserts and updates of the referencing table report the missing row,
+                // changes of the referenced table the rows still pointing to it
+                Some(FOREIGN_KEY_VIOLATION) if err.message().starts_with("insert or update") => {
+                    DatabaseFailure::MissingReference
+                }
+                Some(FOREIGN_KEY_VIOLATION) => DatabaseFailure::StillReferenced,
+                Some(SERIALIZATION_FAILURE) | Some(DEADLOCK_DETECTED) => {
+                    DatabaseFailure::Contention
                 }
+                _ => DatabaseFailure::InvalidData,
+            },
+            _ => DatabaseFailure::Internal,
+        }
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            DatabaseFailure::NotFound => "resource_not_found",
+            DatabaseFailure::Duplicate(_) => "resource_already_exists",
+            DatabaseFailure::MissingReference => "referenced_resource_not_found",
+            DatabaseFailure::StillReferenced => "resource_still_referenced",
+            DatabaseFailure::Contention => "transaction_conflict",
+            DatabaseFailure::Unavailable => "database_unavailable",
+            DatabaseFailure::InvalidData => "invalid_data",
+            DatabaseFailure::Internal => "database_error",
+        }
+    }
+
+    fn problem(self) -> Problem {
+        let code = self.code();
+        match self {
+            DatabaseFailure::NotFound => Problem::new(StatusCode::NOT_FOUND, code),
+            DatabaseFailure::Duplicate(field) => Problem {
+                errors: field
+                    .map(|field| vec![FieldError::localized(field, "field_taken", &[])])
+                    .unwrap_or_default(),
+                ..Problem::new(StatusCode::CONFLICT, code)
             },
-            _ => {
-                Ok(warp::reply::with_status(
-                    "Cannot update data".to_string(),
-                    StatusCode::UNPROCESSABLE_ENTITY,
-                ))
+            DatabaseFailure::MissingReference => Problem::new(StatusCode::NOT_FOUND, code),
+            DatabaseFailure::StillReferenced => Problem::new(StatusCode::CONFLICT, code),
+            DatabaseFailure::Contention => Problem {
+                retry_after: Some(CONTENTION_RETRY_AFTER),
+                ..Problem::new(StatusCode::SERVICE_UNAVAILABLE, code)
+            },
+            DatabaseFailure::Unavailable => Problem {
+                retry_after: Some(UNAVAILABLE_RETRY_AFTER),
+                ..Problem::new(StatusCode::SERVICE_UNAVAILABLE, code)
+            },
+            DatabaseFailure::InvalidData => Problem::new(StatusCode::UNPROCESSABLE_ENTITY, code),
+            DatabaseFailure::Internal => Problem::new(StatusCode::INTERNAL_SERVER_ERROR, code),
+        }
+    }
+}
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Problem details document (RFC 7807). `title` is the reason phrase of the
+/// status, as the `type` is always `about:blank`. The detail is the catalog
+/// message of the code, so it can be translated while the code stays the same.
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    #[serde(serialize_with = "serialize_status")]
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    /// Sent as `Retry-After` header
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+    /// Arguments of the detail message
+    #[serde(skip)]
+    args: Vec<(String, String)>,
+    /// Sent as `Content-Language` header once localized
+    #[serde(skip)]
+    locale: Option<Locale>,
+}
+
+fn serialize_status<S: serde::Serializer>(status: &StatusCode, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u16(status.as_u16())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProblemFormat {
+    Json,
+    PlainText,
+}
+
+impl ProblemFormat {
+    /// Plain text is only chosen if the client rates it higher than JSON,
+    /// a missing or wildcard `Accept` header gets the problem document
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let accept = accept.unwrap_or("*/*");
+        let json = quality(accept, &[PROBLEM_JSON, "application/json"]);
+        let text = quality(accept, &["text/plain"]);
+
+        if text > json {
+            ProblemFormat::PlainText
+        } else {
+            ProblemFormat::Json
+        }
+    }
+}
+
+/// Quality the `Accept` header gives the media types, taken from
+/// the most specific matching range
+fn quality(accept: &str, media_types: &[&str]) -> f32 {
+    let mut best: Option<(u8, f32)> = None;
+
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let range_type = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let specificity = if media_types.contains(&range_type.as_str()) {
+            2
+        } else if range_type == "*/*" {
+            0
+        } else if media_types
+            .iter()
+            .any(|media_type| range_type.strip_suffix("/*") == media_type.split('/').next())
+        {
+            1
+        } else {
+            continue;
+        };
+
+        best = match best {
+            Some((s, q)) if s > specificity || (s == specificity && q >= quality) => Some((s, q)),
+            _ => Some((specificity, quality)),
+        };
+    }
+
+    best.map(|(_, q)| q).unwrap_or(0.0)
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &'static str) -> Self {
+        Problem::with_args(status, code, &[])
+    }
+
+    /// For details with placeholders, like the reason a parameter was rejected
+    pub fn with_args(status: StatusCode, code: &'static str, args: &[(&str, String)]) -> Self {
+        let args = messages::message_args(args);
+        Problem {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status,
+            code,
+            detail: Locale::DEFAULT
+                .message(code, &args)
+                .unwrap_or_else(|| code.to_string()),
+            request_id: None,
+            errors: Vec::new(),
+            retry_after: None,
+            args,
+            locale: None,
+        }
+    }
+
+    /// Translates the detail and the messages of the invalid fields
+    pub fn localize(&mut self, locale: Locale) {
+        if let Some(detail) = locale.message(self.code, &self.args) {
+            self.detail = detail;
+        }
+        for error in &mut self.errors {
+            error.localize(locale);
+        }
+        self.locale = Some(locale);
+    }
+
+    /// The detail followed by the invalid fields, one per line
+    pub fn plain_text(&self) -> String {
+        let mut text = self.detail.clone();
+        for error in &self.errors {
+            text.push_str(&format!("\n{}: {}", error.field, error.message));
+        }
+        if let Some(request_id) = &self.request_id {
+            text.push_str(&format!("\nRequest id: {}", request_id));
+        }
+        text
+    }
+
+    pub fn render(self, format: ProblemFormat) -> Response {
+        let mut response = match format {
+            ProblemFormat::Json => {
+                let body = serde_json::to_string(&self).unwrap_or_default();
+                let mut response = Response::new(body.into());
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+                response
+            }
+            ProblemFormat::PlainText => {
+                let mut response = Response::new(self.plain_text().into());
+                response.headers_mut().insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                );
+                response
             }
+        };
+
+        *response.status_mut() = self.status;
+        if let Some(language) = self
+            .locale
+            .and_then(|locale| HeaderValue::from_str(&locale.language()).ok())
+        {
+            response.headers_mut().insert(CONTENT_LANGUAGE, language);
+        }
+        if let Some(retry_after) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
         }
-    } else if let Some(crate::Error::ReqwestAPIError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::Unauthorized) = r.find() {
-        event!(Level::ERROR, "Not matching account id");
-        Ok(warp::reply::with_status(
-            "No permission to change underlying resource".to_string(),
-            StatusCode::UNAUTHORIZED,
-        ))
-    } else if let Some(crate::Error::WrongPassword) = r.find() {
-        event!(Level::ERROR, "Entered wrong password");
-        Ok(warp::reply::with_status(
-            "Wrong E-Mail/Password combination".to_string(),
-            StatusCode::UNAUTHORIZED,
-        ))
-    } else if let Some(crate::Error::MiddlewareReqwestAPIError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::ClientError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::ServerError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+
+        response
+    }
+}
+
+impl Reply for Problem {
+    fn into_response(self) -> Response {
+        self.render(ProblemFormat::Json)
+    }
+}
+
+#[instrument]
+pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
+    Ok(problem(&r))
+}
+
+/// Recovers rejections of `routes` like `return_error`, but answers in the
+/// format and language the client accepts and with the id of the failed request
+pub fn recover_problems<F, R, I>(
+    routes: F,
+    request_id: I,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+    I: Filter<Extract = (String,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    enum Outcome {
+        Reply(Response),
+        Rejected(Rejection),
+    }
+
+    let routes = routes
+        .map(|reply: R| Outcome::Reply(reply.into_response()))
+        .or_else(|r| async move { Ok::<_, Infallible>((Outcome::Rejected(r),)) });
+
+    request_id
+        .and(warp::header::headers_cloned())
+        .and(routes)
+        .map(|request_id: String, headers: HeaderMap, outcome| match outcome {
+            Outcome::Reply(response) => response,
+            Outcome::Rejected(r) => {
+                let span = tracing::info_span!("problem", request_id = request_id.as_str());
+                let mut problem = span.in_scope(|| problem(&r));
+                problem.request_id = Some(request_id);
+
+                let accept = headers.get(ACCEPT).and_then(|value| value.to_str().ok());
+                let accept_language = headers
+                    .get(ACCEPT_LANGUAGE)
+                    .and_then(|value| value.to_str().ok());
+                problem.localize(Locale::negotiate(accept_language));
+                problem.render(ProblemFormat::negotiate(accept))
+            }
+        })
+}
+
+/// Maps a rejection to the problem reported to the client
+pub fn problem(r: &Rejection) -> Problem {
+    if let Some(error) = r.find::<Error>() {
+        error_problem(error)
     } else if let Some(error) = r.find::<CorsForbidden>() {
         event!(Level::ERROR, "CORS forbidden error: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
+        Problem::with_args(
             StatusCode::FORBIDDEN,
-        ))
+            "cors_forbidden",
+            &[("reason", error.to_string())],
+        )
     } else if let Some(error) = r.find::<BodyDeserializeError>() {
         event!(Level::ERROR, "Cannot deserizalize request body: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ))
-    } else if let Some(error) = r.find::<Error>() {
-        event!(Level::ERROR, "{}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
+        Problem::with_args(
             StatusCode::UNPROCESSABLE_ENTITY,
-        ))
+            "invalid_body",
+            &[("reason", error.to_string())],
+        )
     } else {
         event!(Level::WARN, "Requested route was not found");
-        Ok(warp::reply::with_status(
-            "Route not found".to_string(),
-            StatusCode::NOT_FOUND,
-        ))
+        Problem::new(StatusCode::NOT_FOUND, "not_found")
+    }
+}
+
+fn error_problem(error: &Error) -> Problem {
+    let code = error.code();
+    let internal_error = || Problem::new(StatusCode::INTERNAL_SERVER_ERROR, code);
+
+    match error {
+        Error::DatabaseQueryError(e) => {
+            let failure = DatabaseFailure::of(e);
+            match failure {
+                DatabaseFailure::NotFound | DatabaseFailure::Duplicate(_) => {
+                    event!(Level::WARN, "Database query error: {}", e)
+                }
+                _ => event!(Level::ERROR, "Database query error: {}", e),
+            }
+            failure.problem()
+        }
+        Error::ReqwestAPIError(e) => {
+            event!(Level::ERROR, "{}", e);
+            internal_error()
+        }
+        Error::Unauthorized => {
+            event!(Level::WARN, "Not matching account id");
+            Problem::new(StatusCode::FORBIDDEN, code)
+        }
+        Error::WrongPassword => {
+            event!(Level::ERROR, "Entered wrong password");
+            Problem::new(StatusCode::UNAUTHORIZED, code)
+        }
+        Error::MiddlewareReqwestAPIError(e) => {
+            event!(Level::ERROR, "{}", e);
+            internal_error()
+        }
+        Error::ClientError(e) => {
+            event!(Level::ERROR, "{}", e);
+            internal_error()
+        }
+        Error::ServerError(e) => {
+            event!(Level::ERROR, "{}", e);
+            internal_error()
+        }
+        Error::EmailNotVerified => {
+            event!(Level::ERROR, "Account email is not verified");
+            Problem::new(StatusCode::FORBIDDEN, code)
+        }
+        Error::TooManyRequests(retry_after) => {
+            event!(Level::WARN, "Request throttled for {} seconds", retry_after);
+            Problem {
+                retry_after: Some(*retry_after),
+                ..Problem::new(StatusCode::TOO_MANY_REQUESTS, code)
+            }
+        }
+        Error::EmailAlreadyInUse => {
+            event!(Level::ERROR, "Email address is already in use");
+            Problem::new(StatusCode::CONFLICT, code)
+        }
+        Error::MailError(e) => {
+            event!(Level::ERROR, "{}", e);
+            internal_error()
+        }
+        Error::IoError(e) => {
+            event!(Level::ERROR, "{}", e);
+            internal_error()
+        }
+        Error::ConfigError(e) => {
+            event!(Level::ERROR, "{}", e);
+            internal_error()
+        }
+        Error::ValidationError(errors) => {
+            event!(Level::WARN, "Invalid input: {:?}", errors);
+            Problem {
+                errors: errors.clone(),
+                ..Problem::new(StatusCode::UNPROCESSABLE_ENTITY, code)
+            }
+        }
+        Error::OidcError(e) => {
+            event!(Level::WARN, "OpenID Connect login failed: {}", e);
+            Problem::new(StatusCode::UNAUTHORIZED, code)
+        }
+        Error::TwoFactorAlreadyEnabled => {
+            event!(Level::WARN, "Two-factor authentication is already enabled");
+            Problem::new(StatusCode::CONFLICT, code)
+        }
+        Error::TwoFactorNotEnabled => {
+            event!(Level::WARN, "Two-factor authentication is not set up");
+            Problem::new(StatusCode::CONFLICT, code)
+        }
+        Error::InvalidTwoFactorCode => {
+            event!(Level::WARN, "Entered invalid authentication code");
+            Problem::new(StatusCode::UNAUTHORIZED, code)
+        }
+        Error::ModerationUnavailable(retry_after) => {
+            event!(Level::WARN, "Content rejected while moderation is unavailable");
+            Problem {
+                retry_after: Some(*retry_after),
+                ..Problem::new(StatusCode::SERVICE_UNAVAILABLE, code)
+            }
+        }
+        Error::ReauthenticationRequired => {
+            event!(Level::WARN, "Sign-in is too old to confirm the change");
+            Problem::new(StatusCode::UNAUTHORIZED, code)
+        }
+        Error::ParseError(e) => {
+            event!(Level::ERROR, "{}", error);
+            Problem::with_args(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                code,
+                &[("reason", e.to_string())],
+            )
+        }
+        Error::MissingParameters => {
+            event!(Level::WARN, "{}", error);
+            Problem::new(StatusCode::BAD_REQUEST, code)
+        }
+        Error::CannotDecryptToken => {
+            event!(Level::WARN, "{}", error);
+            Problem::new(StatusCode::UNAUTHORIZED, code)
+        }
+        Error::ArgonLibraryError(e) => {
+            event!(Level::ERROR, "{}: {}", error, e);
+            internal_error()
+        }
+        Error::MigrationError(_) => {
+            event!(Level::ERROR, "{}", error);
+            internal_error()
+        }
+    }
+}
+
+#[cfg(test)]
+mod problem_tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_problem_format() {
+        let format = |accept| ProblemFormat::negotiate(accept);
+
+        assert_eq!(format(None), ProblemFormat::Json);
+        assert_eq!(format(Some("*/*")), ProblemFormat::Json);
+        assert_eq!(format(Some("application/problem+json")), ProblemFormat::Json);
+        assert_eq!(format(Some("text/plain")), ProblemFormat::PlainText);
+        assert_eq!(format(Some("text/*, */*;q=0.1")), ProblemFormat::PlainText);
+        assert_eq!(
+            format(Some("text/plain;q=0.5, application/json")),
+            ProblemFormat::Json
+        );
+        assert_eq!(
+            format(Some("application/json;q=0.2, text/plain;q=0.9")),
+            ProblemFormat::PlainText
+        );
+        assert_eq!(format(Some("text/html")), ProblemFormat::Json);
+        assert_eq!(format(Some("*/*, text/plain;q=0")), ProblemFormat::Json);
+    }
+
+    #[test]
+    fn maps_database_errors() {
+        let not_found = problem(&warp::reject::custom(Error::DatabaseQueryError(
+            sqlx::Error::RowNotFound,
+        )));
+        assert_eq!(not_found.status, StatusCode::NOT_FOUND);
+        assert_eq!(not_found.code, "resource_not_found");
+
+        let error = Error::DatabaseQueryError(sqlx::Error::PoolTimedOut);
+        assert_eq!(error.code(), "database_unavailable");
+        let response = problem(&warp::reject::custom(error)).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+
+        let internal = problem(&warp::reject::custom(Error::DatabaseQueryError(
+            sqlx::Error::ColumnNotFound("id".to_string()),
+        )));
+        assert_eq!(internal.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn maps_auth_errors() {
+        let status = |error| problem(&warp::reject::custom(error)).status;
+
+        assert_eq!(status(Error::CannotDecryptToken), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Error::WrongPassword), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Error::Unauthorized), StatusCode::FORBIDDEN);
+        assert_eq!(status(Error::MissingParameters), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status(Error::ArgonLibraryError(ArgonError::DecodingFail)),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn renders_validation_problem() {
+        let rejection = warp::reject::custom(Error::ValidationError(vec![FieldError::new(
+            "title",
+            "must not be empty",
+        )]));
+        let mut validation = problem(&rejection);
+        validation.request_id = Some("abc".to_string());
+
+        assert_eq!(
+            serde_json::to_value(&validation).unwrap(),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Unprocessable Entity",
+                "status": 422,
+                "code": "validation_failed",
+                "detail": "Invalid input",
+                "request_id": "abc",
+                "errors": [{ "field": "title", "message": "must not be empty" }],
+            })
+        );
+        assert_eq!(
+            validation.plain_text(),
+            "Invalid input\ntitle: must not be empty\nRequest id: abc"
+        );
+
+        let response = validation.render(ProblemFormat::PlainText);
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn localizes_problem() {
+        let rejection = warp::reject::custom(Error::ValidationError(vec![
+            FieldError::localized("title", "field_empty", &[]),
+            FieldError::localized("tags", "field_too_many_tags", &[("max", "10".to_string())]),
+            FieldError::localized("tags", "no_such_message", &[]),
+        ]));
+        let mut validation = problem(&rejection);
+        assert_eq!(validation.detail, "Invalid input");
+        assert_eq!(validation.errors[1].message, "must not contain more than 10 tags");
+        assert_eq!(validation.errors[2].message, "is invalid");
+
+        validation.localize(Locale::negotiate(Some("de-CH, en;q=0.8")));
+        assert_eq!(validation.code, "validation_failed");
+        assert_eq!(
+            validation.plain_text(),
+            "Ungültige Eingabe\ntitle: darf nicht leer sein\n\
+            tags: darf höchstens 10 Tags enthalten\ntags: ist ungültig"
+        );
+        let response = validation.into_response();
+        assert_eq!(response.headers()[CONTENT_LANGUAGE], "de");
+
+        let mut parse = problem(&warp::reject::custom(Error::ParseError(
+            "x".parse::<i32>().unwrap_err(),
+        )));
+        assert_eq!(
+            parse.detail,
+            "Cannot parse parameter: invalid digit found in string"
+        );
+        parse.localize(Locale::negotiate(Some("fr")));
+        assert_eq!(
+            parse.detail,
+            "Cannot parse parameter: invalid digit found in string"
+        );
     }
 }
@@ -0,0 +1,165 @@
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use fluent_langneg::{negotiate_languages, NegotiationStrategy};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+/// Message catalogs compiled into the binary, the first one is the default.
+/// Every catalog has a message per error code and per field message id.
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en/errors.ftl")),
+    ("de", include_str!("../locales/de/errors.ftl")),
+];
+
+struct Catalogs {
+    locales: Vec<LanguageIdentifier>,
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+fn catalogs() -> &'static Catalogs {
+    static LOADED: OnceLock<Catalogs> = OnceLock::new();
+
+    LOADED.get_or_init(|| {
+        let mut locales = Vec::new();
+        let mut bundles = Vec::new();
+        for (locale, source) in CATALOGS {
+            let Ok(locale) = locale.parse::<LanguageIdentifier>() else {
+                continue;
+            };
+            // Keeps the messages which parsed, the tests catch broken catalogs
+            let resource = FluentResource::try_new(source.to_string())
+                .unwrap_or_else(|(resource, _)| resource);
+            let mut bundle = FluentBundle::new_concurrent(vec![locale.clone()]);
+            // No isolation marks around arguments, they end up in JSON and plain text
+            bundle.set_use_isolating(false);
+            bundle.add_resource_overriding(resource);
+
+            locales.push(locale);
+            bundles.push(bundle);
+        }
+        Catalogs { locales, bundles }
+    })
+}
+
+/// Language of the messages in a response
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Locale(usize);
+
+impl Locale {
+    /// English, also used for log output
+    pub const DEFAULT: Locale = Locale(0);
+
+    /// Best catalog for the `Accept-Language` header, ranges are tried by
+    /// descending quality. Falls back to English if none of them matches.
+    pub fn negotiate(accept_language: Option<&str>) -> Self {
+        let mut ranges = accept_language
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let tag = params.next()?.trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                let locale = tag.parse::<LanguageIdentifier>().ok()?;
+                (quality > 0.0).then_some((locale, quality))
+            })
+            .collect::<Vec<_>>();
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        let requested = ranges
+            .into_iter()
+            .map(|(locale, _)| locale)
+            .collect::<Vec<_>>();
+
+        let available = &catalogs().locales;
+        negotiate_languages(&requested, available, None, NegotiationStrategy::Lookup)
+            .first()
+            .and_then(|chosen| available.iter().position(|locale| locale == *chosen))
+            .map(Locale)
+            .unwrap_or(Locale::DEFAULT)
+    }
+
+    /// Language tag for the `Content-Language` header
+    pub fn language(&self) -> String {
+        catalogs()
+            .locales
+            .get(self.0)
+            .map(|locale| locale.to_string())
+            .unwrap_or_else(|| "en".to_string())
+    }
+
+    /// Renders the message, in English if this catalog lacks it
+    pub fn message(&self, id: &str, args: &[(String, String)]) -> Option<String> {
+        let catalogs = catalogs();
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(name.as_str(), value.as_str());
+        }
+
+        [self.0, Locale::DEFAULT.0]
+            .iter()
+            .filter_map(|index| catalogs.bundles.get(*index))
+            .find_map(|bundle| {
+                let pattern = bundle.get_message(id)?.value()?;
+                let mut errors = Vec::new();
+                let text = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+                errors.is_empty().then(|| text.into_owned())
+            })
+    }
+}
+
+/// Arguments of a message, as owned name/value pairs
+pub(crate) fn message_args(args: &[(&str, String)]) -> Vec<(String, String)> {
+    args.iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod messages_tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_locale() {
+        let language = |accept| Locale::negotiate(accept).language();
+
+        assert_eq!(language(None), "en");
+        assert_eq!(language(Some("de")), "de");
+        assert_eq!(language(Some("de-AT, en;q=0.5")), "de");
+        assert_eq!(language(Some("fr, de;q=0.8, en;q=0.9")), "en");
+        assert_eq!(language(Some("fr")), "en");
+        assert_eq!(language(Some("de;q=0")), "en");
+    }
+
+    #[test]
+    fn catalogs_are_complete() {
+        let ids = |source: &str| {
+            source
+                .lines()
+                .filter(|line| line.starts_with(|c: char| c.is_ascii_lowercase()))
+                .filter_map(|line| line.split_once(" = ").map(|(id, _)| id.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let catalogs = catalogs();
+        assert_eq!(catalogs.bundles.len(), CATALOGS.len());
+        for (bundle, (locale, source)) in catalogs.bundles.iter().zip(CATALOGS) {
+            for id in ids(CATALOGS[0].1) {
+                assert!(bundle.has_message(&id), "{} lacks {}", locale, id);
+            }
+            assert_eq!(ids(source).len(), ids(CATALOGS[0].1).len(), "{}", locale);
+        }
+
+        let german = Locale::negotiate(Some("de"));
+        let args = message_args(&[("max", "10".to_string())]);
+        assert_eq!(
+            german.message("field_too_many_tags", &args).unwrap(),
+            "darf höchstens 10 Tags enthalten"
+        );
+        assert_eq!(
+            Locale::DEFAULT.message("io_error", &[]).unwrap(),
+            "Internal Server Error"
+        );
+        assert_eq!(german.message("unknown", &[]), None);
+    }
+}
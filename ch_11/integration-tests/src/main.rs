@@ -61,7 +61,7 @@ async fn main() -> Result<(), handle_errors::Error> {
     let store = setup_store(&config).await?;
 
     // start the server and listen for a sender signal to shut it down
-    let handler = oneshot(store).await;
+    let handler = oneshot(&config, store).await?;
 
     // create a test user to use throughout the tests
     let u = User {
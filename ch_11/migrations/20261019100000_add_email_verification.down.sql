@@ -0,0 +1,3 @@
+ALTER TABLE accounts
+DROP COLUMN email_verified_at,
+DROP COLUMN verification_sent_at;
@@ -0,0 +1,2 @@
+ALTER TABLE accounts
+ADD COLUMN sessions_revoked_at TIMESTAMP;
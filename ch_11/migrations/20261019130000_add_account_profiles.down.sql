@@ -0,0 +1,5 @@
+ALTER TABLE accounts
+DROP COLUMN display_name,
+DROP COLUMN bio,
+DROP COLUMN website,
+DROP COLUMN created_on;
@@ -0,0 +1,5 @@
+ALTER TABLE accounts
+ADD COLUMN display_name VARCHAR(64),
+ADD COLUMN bio TEXT,
+ADD COLUMN website VARCHAR(255),
+ADD COLUMN created_on TIMESTAMP NOT NULL DEFAULT NOW();
@@ -0,0 +1,5 @@
+-- Authored content of deleted accounts is reassigned to this placeholder.
+-- Its password is not a valid hash, so nobody can ever log in with it.
+INSERT INTO accounts (id, email, password, display_name)
+VALUES (0, 'deleted-user', '!', 'Deleted user')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,3 @@
+-- Fails if two accounts only differ in the case of their email,
+-- those have to be merged or renamed by hand first
+CREATE UNIQUE INDEX IF NOT EXISTS accounts_email_lower_key ON accounts (LOWER(email));
@@ -0,0 +1,10 @@
+-- Logins at external OpenID Connect providers, one account can have several
+CREATE TABLE IF NOT EXISTS account_identities (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    account_id INTEGER NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS account_identities_account_id_idx ON account_identities (account_id);
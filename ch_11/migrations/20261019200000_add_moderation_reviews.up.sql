@@ -0,0 +1,10 @@
+-- Content accepted uncensored while the moderation service was unavailable,
+-- a row is removed once a moderator reviewed the content
+CREATE TABLE IF NOT EXISTS moderation_reviews (
+    id SERIAL PRIMARY KEY,
+    content_type TEXT NOT NULL CHECK (content_type IN ('question', 'answer')),
+    content_id INTEGER NOT NULL,
+    request_id TEXT,
+    queued_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (content_type, content_id)
+);
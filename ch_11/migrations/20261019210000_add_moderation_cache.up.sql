@@ -0,0 +1,9 @@
+-- Censored content by hash of the moderator and the original content,
+-- shared by all instances so the same content is moderated only once
+CREATE TABLE IF NOT EXISTS moderation_cache (
+    content_hash TEXT PRIMARY KEY,
+    censored TEXT NOT NULL,
+    cached_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS moderation_cache_cached_at_idx ON moderation_cache (cached_at);
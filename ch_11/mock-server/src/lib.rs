@@ -1,13 +1,36 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use bytes::Bytes;
+use rand::Rng;
 use serde_json::json;
 use tokio::sync::{oneshot, oneshot::Sender};
 use warp::{http, Filter, Reply};
 
+/// Client id the mock identity provider accepts
+pub const OIDC_CLIENT_ID: &str = "rust-web-dev";
+/// Signs in as this user, unless the authorization request has a `login_hint`
+pub const OIDC_DEFAULT_EMAIL: &str = "mock.user@example.com";
+
+/// Authorization code handed out by the mock identity provider,
+/// waiting to be exchanged at the token endpoint
+#[derive(Clone, Debug)]
+struct PendingCode {
+    client_id: String,
+    redirect_uri: String,
+    code_challenge: String,
+    nonce: Option<String>,
+    email: String,
+    email_verified: bool,
+}
+
+type Codes = Arc<Mutex<HashMap<String, PendingCode>>>;
+
 #[derive(Clone, Debug)]
 pub struct MockServer {
     socket: SocketAddr,
+    codes: Codes,
 }
 
 pub struct OneshotHandler {
@@ -18,9 +41,15 @@ impl MockServer {
     pub fn new(bind_addr: SocketAddr) -> MockServer {
         MockServer {
             socket: bind_addr,
+            codes: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Issuer of the mock identity provider, `iss` of its ID tokens
+    pub fn oidc_issuer(&self) -> String {
+        format!("http://{}/oidc", self.socket)
+    }
+
     async fn check_profanity(
         _: (),
         content: Bytes,
@@ -58,14 +87,140 @@ impl MockServer {
         }
     }
 
+    /// Skips the login page and consent screen, and redirects back
+    /// to the client right away
+    async fn oidc_authorize(
+        params: HashMap<String, String>,
+        codes: Codes,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let required = ["client_id", "redirect_uri", "state", "code_challenge"];
+        if required.iter().any(|name| !params.contains_key(*name))
+            || params.get("response_type").map(String::as_str) != Some("code")
+            || params.get("code_challenge_method").map(String::as_str) != Some("S256")
+            || params["client_id"] != OIDC_CLIENT_ID
+        {
+            return Ok(http::Response::builder()
+                .status(http::StatusCode::BAD_REQUEST)
+                .body("invalid_request".to_string())
+                .unwrap());
+        }
+
+        let code = random_string();
+        codes.lock().unwrap().insert(
+            code.clone(),
+            PendingCode {
+                client_id: params["client_id"].clone(),
+                redirect_uri: params["redirect_uri"].clone(),
+                code_challenge: params["code_challenge"].clone(),
+                nonce: params.get("nonce").cloned(),
+                email: params
+                    .get("login_hint")
+                    .cloned()
+                    .unwrap_or_else(|| OIDC_DEFAULT_EMAIL.to_string()),
+                // Not part of OpenID Connect, lets tests sign in with an unverified email
+                email_verified: params.get("email_verified").map(String::as_str) != Some("false"),
+            },
+        );
+
+        let separator = if params["redirect_uri"].contains('?') { '&' } else { '?' };
+        let location = format!(
+            "{}{}code={}&state={}",
+            params["redirect_uri"], separator, code, params["state"]
+        );
+
+        Ok(http::Response::builder()
+            .status(http::StatusCode::FOUND)
+            .header(http::header::LOCATION, location)
+            .body(String::new())
+            .unwrap())
+    }
+
+    async fn oidc_token(
+        codes: Codes,
+        issuer: String,
+        form: HashMap<String, String>,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let pending = form
+            .get("code")
+            .and_then(|code| codes.lock().unwrap().remove(code));
+
+        let pending = match pending {
+            Some(pending)
+                if form.get("grant_type").map(String::as_str) == Some("authorization_code")
+                    && form.get("client_id") == Some(&pending.client_id)
+                    && form.get("redirect_uri") == Some(&pending.redirect_uri)
+                    && form.get("code_verifier").map(|v| code_challenge(v.as_str()))
+                        == Some(pending.code_challenge.clone()) =>
+            {
+                pending
+            }
+            _ => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({ "error": "invalid_grant" })),
+                    http::StatusCode::BAD_REQUEST,
+                ))
+            }
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let claims = json!({
+            "iss": issuer,
+            "sub": format!("mock|{}", pending.email),
+            "aud": pending.client_id,
+            "exp": now + 300,
+            "iat": now,
+            "nonce": pending.nonce,
+            "email": pending.email,
+            "email_verified": pending.email_verified,
+        });
+        // Unsigned, the client receives it straight from the token endpoint
+        let id_token = format!(
+            "{}.{}.",
+            base64::encode_config(r#"{"alg":"none","typ":"JWT"}"#, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(claims.to_string(), base64::URL_SAFE_NO_PAD)
+        );
+
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "access_token": random_string(),
+                "token_type": "Bearer",
+                "expires_in": 300,
+                "id_token": id_token,
+            })),
+            http::StatusCode::OK,
+        ))
+    }
+
     fn build_routes(&self) -> impl Filter<Extract = impl Reply> + Clone {
-        warp::post()
+        let codes = self.codes.clone();
+        let codes_filter = warp::any().map(move || codes.clone());
+        let issuer = self.oidc_issuer();
+
+        let bad_words = warp::post()
             .and(warp::path("bad_words"))
             .and(warp::query())
             .map(|_: HashMap<String, String>| ())
             .and(warp::path::end())
             .and(warp::body::bytes())
-            .and_then(Self::check_profanity)
+            .and_then(Self::check_profanity);
+
+        let oidc_authorize = warp::get()
+            .and(warp::path!("oidc" / "authorize"))
+            .and(warp::query())
+            .and(codes_filter.clone())
+            .and_then(Self::oidc_authorize);
+
+        let oidc_token = warp::post()
+            .and(warp::path!("oidc" / "token"))
+            .and(codes_filter)
+            .and(warp::any().map(move || issuer.clone()))
+            .and(warp::body::form())
+            .and_then(Self::oidc_token);
+
+        bad_words.or(oidc_authorize).or(oidc_token)
     }
 
     pub fn oneshot(&self) -> OneshotHandler {
@@ -83,3 +238,12 @@ impl MockServer {
         }
     }
 }
+
+fn random_string() -> String {
+    base64::encode_config(rand::thread_rng().gen::<[u8; 32]>(), base64::URL_SAFE_NO_PAD)
+}
+
+fn code_challenge(code_verifier: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, code_verifier.as_bytes());
+    base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD)
+}
@@ -10,7 +10,5 @@ async fn main() -> Result<(), handle_errors::Error> {
 
     tracing::info!("Q&A service build ID {}", env!("RUST_WEB_DEV_VERSION"));
 
-    run(config, store).await;
-
-    Ok(())
+    run(config, store).await
 }
@@ -0,0 +1,171 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::{event, Level};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    /// Calls are rejected without trying
+    Open,
+    /// A single probe call decides whether the circuit closes again
+    HalfOpen,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probe_started: Option<Instant>,
+}
+
+/// Stops calling a failing service for a while. The circuit opens after
+/// `failure_threshold` failures in a row, and once `open_duration` passed
+/// a single probe call is let through. Its success closes the circuit,
+/// its failure opens it again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: &'static str,
+    failure_threshold: u32,
+    open_duration: Duration,
+    circuit: Mutex<Circuit>,
+    times_opened: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, failure_threshold: u32, open_duration: Duration) -> Self {
+        CircuitBreaker {
+            name,
+            failure_threshold,
+            open_duration,
+            circuit: Mutex::new(Circuit::default()),
+            times_opened: AtomicU64::new(0),
+        }
+    }
+
+    fn circuit(&self) -> std::sync::MutexGuard<'_, Circuit> {
+        self.circuit.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the call may go through, every allowed call has to
+    /// report its outcome with `record_success` or `record_failure`
+    pub fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut circuit = self.circuit();
+
+        match circuit.opened_at {
+            None => true,
+            Some(opened_at) if now < opened_at + self.open_duration => false,
+            Some(_) => match circuit.probe_started {
+                // A probe which never reported back, e.g. as its request was cancelled,
+                // is given up after the open duration
+                Some(started) if now < started + self.open_duration => false,
+                _ => {
+                    circuit.probe_started = Some(now);
+                    true
+                }
+            },
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut circuit = self.circuit();
+        if circuit.opened_at.is_some() {
+            event!(Level::INFO, breaker = self.name, "circuit breaker closed");
+        }
+        *circuit = Circuit::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut circuit = self.circuit();
+        circuit.failures = circuit.failures.saturating_add(1);
+
+        let probe_failed = circuit.probe_started.is_some();
+        if probe_failed
+            || (circuit.opened_at.is_none() && circuit.failures >= self.failure_threshold)
+        {
+            circuit.opened_at = Some(Instant::now());
+            circuit.probe_started = None;
+            self.times_opened.fetch_add(1, Ordering::Relaxed);
+            event!(
+                Level::WARN,
+                breaker = self.name,
+                failures = circuit.failures,
+                "circuit breaker opened"
+            );
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let circuit = self.circuit();
+        match circuit.opened_at {
+            None => CircuitState::Closed,
+            Some(_) if circuit.probe_started.is_some() => CircuitState::HalfOpen,
+            Some(opened_at) if opened_at.elapsed() >= self.open_duration => CircuitState::HalfOpen,
+            Some(_) => CircuitState::Open,
+        }
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.circuit().failures
+    }
+
+    pub fn times_opened(&self) -> u64 {
+        self.times_opened.load(Ordering::Relaxed)
+    }
+
+    /// Seconds until the next call may go through, rounded up and at least one
+    pub fn retry_after(&self) -> u64 {
+        let circuit = self.circuit();
+        let wait = circuit
+            .opened_at
+            .map(|opened_at| {
+                (opened_at + self.open_duration).saturating_duration_since(Instant::now())
+            })
+            .unwrap_or_default();
+        wait.as_secs() + 1
+    }
+}
+
+#[cfg(test)]
+mod circuit_breaker_tests {
+    use super::*;
+
+    #[test]
+    fn opens_and_probes() {
+        let breaker = CircuitBreaker::new("test", 2, Duration::from_millis(50));
+
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+        assert_eq!(breaker.times_opened(), 1);
+        assert_eq!(breaker.retry_after(), 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.times_opened(), 2);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.consecutive_failures(), 0);
+        assert!(breaker.allow());
+    }
+}
@@ -2,7 +2,7 @@ use clap::Parser;
 use std::env;
 
 /// Q&A web service API
-#[derive(Parser, Debug, PartialEq)]
+#[derive(Parser, Debug, Clone, PartialEq)]
 #[clap(author, version, about, long_about = None)]
 pub struct Config {
     /// Which errors we want to log (info, warn or error)
@@ -26,17 +26,153 @@ pub struct Config {
     /// Database name
     #[clap(long, default_value = "rustwebdev")]
     pub db_name: String,
+    /// Public base URL of the service, used to build links in emails
+    #[clap(long, default_value = "http://localhost:8080")]
+    pub public_url: String,
+    /// Reject new questions and answers from accounts with an unverified email
+    #[clap(long)]
+    pub require_verified_email: bool,
+    /// Minimum number of seconds between two verification emails
+    #[clap(long, default_value = "300")]
+    pub verification_resend_interval: i64,
+    /// SMTP server used to send emails, they are only logged if unset
+    #[clap(long)]
+    pub smtp_host: Option<String>,
+    /// Sender address of outgoing emails
+    #[clap(long, default_value = "Q&A Service <noreply@localhost>")]
+    pub mail_from: String,
+    /// Failed logins for one email before it gets locked out
+    #[clap(long, default_value = "5")]
+    pub login_max_attempts: u32,
+    /// Failed logins from one IP address before it gets locked out
+    #[clap(long, default_value = "20")]
+    pub login_max_attempts_per_ip: u32,
+    /// Duration of the first lockout in seconds, doubled with every further failure
+    #[clap(long, default_value = "30")]
+    pub login_lockout_seconds: u64,
+    /// Upper bound for the lockout duration in seconds
+    #[clap(long, default_value = "900")]
+    pub login_max_lockout_seconds: u64,
+    /// Minimum number of characters of a password
+    #[clap(long, default_value = "8")]
+    pub password_min_length: usize,
+    /// Maximum number of characters of a password
+    #[clap(long, default_value = "128")]
+    pub password_max_length: usize,
+    /// File with known breached passwords (one per line), which are rejected
+    #[clap(long)]
+    pub breached_passwords_file: Option<String>,
+    /// Argon2 variant used for new password hashes (argon2i, argon2d or argon2id)
+    #[clap(long, default_value = "argon2id")]
+    pub argon2_variant: String,
+    /// Argon2 memory cost in KiB
+    #[clap(long, default_value = "19456")]
+    pub argon2_memory_cost: u32,
+    /// Argon2 number of iterations
+    #[clap(long, default_value = "2")]
+    pub argon2_iterations: u32,
+    /// Argon2 degree of parallelism
+    #[clap(long, default_value = "1")]
+    pub argon2_parallelism: u32,
+    /// Also hand out the session as HttpOnly cookie on login, for browser clients
+    #[clap(long)]
+    pub session_cookies: bool,
+    /// Format of issued tokens: v2.local (shared secret) or v4.public (Ed25519 signature).
+    /// For v4.public, keys are base64 encoded 32 byte Ed25519 seeds.
+    #[clap(long, default_value = "v2.local")]
+    pub token_format: String,
+    /// JSON file with the current and previous PASETO keys, replaces PASETO_KEY.
+    /// The file is read again when the process receives SIGHUP.
+    #[clap(long)]
+    pub paseto_keys_file: Option<String>,
+    /// Issuer of the OpenID Connect provider, enables `/auth/oidc/*` when set.
+    /// The client secret is read from OIDC_CLIENT_SECRET.
+    #[clap(long)]
+    pub oidc_issuer: Option<String>,
+    /// Authorization endpoint of the OpenID Connect provider
+    #[clap(long)]
+    pub oidc_authorization_endpoint: Option<String>,
+    /// Token endpoint of the OpenID Connect provider
+    #[clap(long)]
+    pub oidc_token_endpoint: Option<String>,
+    /// Client id registered with the OpenID Connect provider
+    #[clap(long)]
+    pub oidc_client_id: Option<String>,
+    /// Scopes requested from the OpenID Connect provider
+    #[clap(long, default_value = "openid email profile")]
+    pub oidc_scopes: String,
+    /// Issuer shown in authenticator apps for two-factor authentication
+    #[clap(long, default_value = "Q&A Service")]
+    pub totp_issuer: String,
+    /// Censors questions and answers: apilayer (needs BAD_WORDS_API_KEY and
+    /// API_LAYER_URL), word-list or none
+    #[clap(long, default_value = "apilayer")]
+    pub content_moderator: String,
+    /// File with the words censored by the word-list moderator, one per line
+    #[clap(long)]
+    pub moderation_word_list: Option<String>,
+    /// What happens to content while the moderator fails: fail-closed (rejected),
+    /// fail-open (stored uncensored and queued for review) or word-list
+    /// (censored with `--moderation-word-list`)
+    #[clap(long, default_value = "fail-closed")]
+    pub moderation_failure_policy: String,
+    /// Failed moderation calls in a row before calls are stopped for a while
+    #[clap(long, default_value = "5")]
+    pub moderation_breaker_failures: u32,
+    /// Seconds without moderation calls after the failure limit was reached,
+    /// then a single call probes whether the service recovered
+    #[clap(long, default_value = "30")]
+    pub moderation_breaker_open_seconds: u64,
+    /// Moderation results kept in memory, 0 disables the in-memory cache
+    #[clap(long, default_value = "1000")]
+    pub moderation_cache_size: usize,
+    /// Seconds a moderation result is reused for the same content
+    #[clap(long, default_value = "3600")]
+    pub moderation_cache_ttl_seconds: u64,
+    /// Also cache moderation results in Postgres, shared by all instances
+    #[clap(long)]
+    pub moderation_cache_db: bool,
+    /// Milliseconds to wait for a connection to an external service
+    #[clap(long, default_value = "2000")]
+    pub http_connect_timeout_ms: u64,
+    /// Milliseconds an attempt of a call to an external service may take
+    #[clap(long, default_value = "5000")]
+    pub http_request_timeout_ms: u64,
+    /// Retries of calls to external services failing with a transient error
+    #[clap(long, default_value = "3")]
+    pub http_max_retries: u32,
+    /// Backoff before the first retry in milliseconds, doubled with every further retry
+    #[clap(long, default_value = "100")]
+    pub http_retry_min_backoff_ms: u64,
+    /// Upper bound for the backoff between retries in milliseconds
+    #[clap(long, default_value = "2000")]
+    pub http_retry_max_backoff_ms: u64,
+    /// Calls to external services in flight at the same time, further calls wait
+    #[clap(long, default_value = "32")]
+    pub http_max_concurrent_requests: usize,
 }
 
 impl Config {
     pub fn new() -> Result<Config, handle_errors::Error> {
         let config = Config::parse();
 
-        if env::var("BAD_WORDS_API_KEY").is_err() {
+        let content_moderator =
+            env::var("CONTENT_MODERATOR").unwrap_or_else(|_| config.content_moderator.to_owned());
+        if content_moderator == "apilayer" && env::var("BAD_WORDS_API_KEY").is_err() {
             panic!("BadWords API key not set");
         }
 
-        if env::var("PASETO_KEY").is_err() {
+        let paseto_keys_file = env::var("PASETO_KEYS_FILE")
+            .ok()
+            .or(config.paseto_keys_file);
+
+        let session_cookies = env::var("SESSION_COOKIES")
+            .map(|val| val == "true" || val == "1")
+            .unwrap_or(config.session_cookies);
+        let token_format =
+            env::var("TOKEN_FORMAT").unwrap_or_else(|_| config.token_format.to_owned());
+
+        if env::var("PASETO_KEY").is_err() && paseto_keys_file.is_none() {
             panic!("PASETO_KEY not set");
         }
 
@@ -52,6 +188,32 @@ impl Config {
         let db_port = env::var("POSTGRES_PORT").unwrap_or_else(|_| config.db_port.to_string());
         let db_name = env::var("POSTGRES_DB").unwrap_or_else(|_| config.db_name.to_owned());
 
+        let public_url = env::var("PUBLIC_URL").unwrap_or_else(|_| config.public_url.to_owned());
+        let require_verified_email = env::var("REQUIRE_VERIFIED_EMAIL")
+            .map(|val| val == "true" || val == "1")
+            .unwrap_or(config.require_verified_email);
+        let smtp_host = env::var("SMTP_HOST").ok().or(config.smtp_host);
+        let mail_from = env::var("MAIL_FROM").unwrap_or_else(|_| config.mail_from.to_owned());
+        let breached_passwords_file = env::var("BREACHED_PASSWORDS_FILE")
+            .ok()
+            .or(config.breached_passwords_file);
+        let oidc_issuer = env::var("OIDC_ISSUER").ok().or(config.oidc_issuer);
+        let oidc_authorization_endpoint = env::var("OIDC_AUTHORIZATION_ENDPOINT")
+            .ok()
+            .or(config.oidc_authorization_endpoint);
+        let oidc_token_endpoint = env::var("OIDC_TOKEN_ENDPOINT")
+            .ok()
+            .or(config.oidc_token_endpoint);
+        let oidc_client_id = env::var("OIDC_CLIENT_ID").ok().or(config.oidc_client_id);
+        let moderation_word_list = env::var("MODERATION_WORD_LIST")
+            .ok()
+            .or(config.moderation_word_list);
+        let moderation_failure_policy = env::var("MODERATION_FAILURE_POLICY")
+            .unwrap_or_else(|_| config.moderation_failure_policy.to_owned());
+        let moderation_cache_db = env::var("MODERATION_CACHE_DB")
+            .map(|val| val == "true" || val == "1")
+            .unwrap_or(config.moderation_cache_db);
+
         Ok(Config {
             log_level: config.log_level,
             port,
@@ -62,6 +224,45 @@ impl Config {
                 .parse::<u16>()
                 .map_err(handle_errors::Error::ParseError)?,
             db_name,
+            public_url,
+            require_verified_email,
+            verification_resend_interval: config.verification_resend_interval,
+            smtp_host,
+            mail_from,
+            login_max_attempts: config.login_max_attempts,
+            login_max_attempts_per_ip: config.login_max_attempts_per_ip,
+            login_lockout_seconds: config.login_lockout_seconds,
+            login_max_lockout_seconds: config.login_max_lockout_seconds,
+            password_min_length: config.password_min_length,
+            password_max_length: config.password_max_length,
+            breached_passwords_file,
+            argon2_variant: config.argon2_variant,
+            argon2_memory_cost: config.argon2_memory_cost,
+            argon2_iterations: config.argon2_iterations,
+            argon2_parallelism: config.argon2_parallelism,
+            session_cookies,
+            token_format,
+            paseto_keys_file,
+            oidc_issuer,
+            oidc_authorization_endpoint,
+            oidc_token_endpoint,
+            oidc_client_id,
+            oidc_scopes: config.oidc_scopes,
+            totp_issuer: config.totp_issuer,
+            content_moderator,
+            moderation_word_list,
+            moderation_failure_policy,
+            moderation_breaker_failures: config.moderation_breaker_failures,
+            moderation_breaker_open_seconds: config.moderation_breaker_open_seconds,
+            moderation_cache_size: config.moderation_cache_size,
+            moderation_cache_ttl_seconds: config.moderation_cache_ttl_seconds,
+            moderation_cache_db,
+            http_connect_timeout_ms: config.http_connect_timeout_ms,
+            http_request_timeout_ms: config.http_request_timeout_ms,
+            http_max_retries: config.http_max_retries,
+            http_retry_min_backoff_ms: config.http_retry_min_backoff_ms,
+            http_retry_max_backoff_ms: config.http_retry_max_backoff_ms,
+            http_max_concurrent_requests: config.http_max_concurrent_requests,
         })
     }
 }
@@ -97,6 +298,45 @@ mod config_tests {
             db_host: "localhost".to_string(),
             db_port: 5432,
             db_name: "rustwebdev".to_string(),
+            public_url: "http://localhost:8080".to_string(),
+            require_verified_email: false,
+            verification_resend_interval: 300,
+            smtp_host: None,
+            mail_from: "Q&A Service <noreply@localhost>".to_string(),
+            login_max_attempts: 5,
+            login_max_attempts_per_ip: 20,
+            login_lockout_seconds: 30,
+            login_max_lockout_seconds: 900,
+            password_min_length: 8,
+            password_max_length: 128,
+            breached_passwords_file: None,
+            argon2_variant: "argon2id".to_string(),
+            argon2_memory_cost: 19456,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            session_cookies: false,
+            token_format: "v2.local".to_string(),
+            paseto_keys_file: None,
+            oidc_issuer: None,
+            oidc_authorization_endpoint: None,
+            oidc_token_endpoint: None,
+            oidc_client_id: None,
+            oidc_scopes: "openid email profile".to_string(),
+            totp_issuer: "Q&A Service".to_string(),
+            content_moderator: "apilayer".to_string(),
+            moderation_word_list: None,
+            moderation_failure_policy: "fail-closed".to_string(),
+            moderation_breaker_failures: 5,
+            moderation_breaker_open_seconds: 30,
+            moderation_cache_size: 1000,
+            moderation_cache_ttl_seconds: 3600,
+            moderation_cache_db: false,
+            http_connect_timeout_ms: 2000,
+            http_request_timeout_ms: 5000,
+            http_max_retries: 3,
+            http_retry_min_backoff_ms: 100,
+            http_retry_max_backoff_ms: 2000,
+            http_max_concurrent_requests: 32,
         };
 
         let config = Config::new().unwrap();
@@ -0,0 +1,93 @@
+use std::{sync::Arc, time::Duration};
+
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, RequestBuilder};
+use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use handle_errors::Error;
+
+use crate::config::Config;
+
+/// Client for calls to external services. Built once at startup, so connections
+/// and TLS sessions are reused across requests. Timeouts apply to every attempt,
+/// timed out and failed attempts are retried with exponential backoff.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: ClientWithMiddleware,
+    /// Bounds the calls in flight, further calls wait for a free slot
+    slots: Arc<Semaphore>,
+}
+
+impl HttpClient {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        if config.http_max_concurrent_requests == 0 {
+            return Err(Error::ConfigError(
+                "http_max_concurrent_requests must be at least 1".to_string(),
+            ));
+        }
+        if config.http_retry_min_backoff_ms > config.http_retry_max_backoff_ms {
+            return Err(Error::ConfigError(
+                "http_retry_min_backoff_ms must not exceed http_retry_max_backoff_ms".to_string(),
+            ));
+        }
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(config.http_connect_timeout_ms))
+            .timeout(Duration::from_millis(config.http_request_timeout_ms))
+            .build()
+            .map_err(Error::ReqwestAPIError)?;
+        let retry_policy = ExponentialBackoff::builder()
+            .retry_bounds(
+                Duration::from_millis(config.http_retry_min_backoff_ms),
+                Duration::from_millis(config.http_retry_max_backoff_ms),
+            )
+            .build_with_max_retries(config.http_max_retries);
+
+        Ok(HttpClient {
+            client: ClientBuilder::new(client)
+                .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+                .build(),
+            slots: Arc::new(Semaphore::new(config.http_max_concurrent_requests)),
+        })
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Waits for a free slot. The call, including reading the response,
+    /// has to happen while the returned permit is alive.
+    pub async fn slot(&self) -> Result<SemaphorePermit<'_>, Error> {
+        self.slots
+            .acquire()
+            .await
+            .map_err(|e| Error::IoError(std::io::Error::other(e)))
+    }
+}
+
+#[cfg(test)]
+mod http_client_tests {
+    use super::*;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn bounds_concurrent_calls() {
+        let config = Config::parse_from(["test", "--http-max-concurrent-requests", "1"]);
+        let client = HttpClient::new(&config).unwrap();
+
+        let slot = client.slot().await.unwrap();
+        let waiting = tokio::time::timeout(Duration::from_millis(50), client.slot()).await;
+        assert!(waiting.is_err());
+        drop(slot);
+        assert!(client.slot().await.is_ok());
+
+        let config = Config::parse_from([
+            "test",
+            "--http-retry-min-backoff-ms",
+            "500",
+            "--http-retry-max-backoff-ms",
+            "100",
+        ]);
+        assert!(HttpClient::new(&config).is_err());
+    }
+}
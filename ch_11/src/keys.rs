@@ -0,0 +1,550 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use paseto::pae::pae;
+use ring::signature::{self, Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use handle_errors::Error;
+
+use crate::config::Config;
+
+/// v2.local tokens are encrypted with XChaCha20-Poly1305, which needs 32 byte keys
+const LOCAL_KEY_LENGTH: usize = 32;
+/// v4.public keys are configured as base64 encoded 32 byte Ed25519 seeds
+const SEED_LENGTH: usize = 32;
+const SIGNATURE_LENGTH: usize = 64;
+const V4_PUBLIC_HEADER: &str = "v4.public.";
+/// Key id of the single key from `PASETO_KEY`
+const ENV_KEY_ID: &str = "default";
+
+/// `v2.local` tokens can only be read by holders of the shared key,
+/// `v4.public` tokens can be verified by everybody with the public key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    V2Local,
+    V4Public,
+}
+
+impl FromStr for TokenFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v2.local" => Ok(TokenFormat::V2Local),
+            "v4.public" => Ok(TokenFormat::V4Public),
+            _ => Err(Error::ConfigError(format!("Unknown token format: {}", s))),
+        }
+    }
+}
+
+/// Layout of the keys file:
+/// `{"current": "2026-10", "keys": {"2026-10": "...", "2026-09": "..."}}`
+#[derive(Deserialize)]
+struct KeysFile {
+    current: String,
+    keys: HashMap<String, String>,
+}
+
+/// Footer of every issued token, so the matching key can be picked without trial and error
+#[derive(Serialize, Deserialize)]
+struct Footer {
+    kid: String,
+}
+
+/// Public key as served at `/.well-known/paseto-keys`, in PASERK notation (`k4.public.…`)
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PublicKey {
+    pub kid: String,
+    pub key: String,
+}
+
+enum Key {
+    Local(Vec<u8>),
+    Public(Ed25519KeyPair),
+}
+
+impl Key {
+    fn parse(format: TokenFormat, id: &str, value: String) -> Result<Self, Error> {
+        match format {
+            TokenFormat::V2Local => {
+                let key = value.into_bytes();
+                if key.len() != LOCAL_KEY_LENGTH {
+                    return Err(Error::ConfigError(format!(
+                        "PASETO key {} has to be {} bytes long, got {}",
+                        id,
+                        LOCAL_KEY_LENGTH,
+                        key.len()
+                    )));
+                }
+                Ok(Key::Local(key))
+            }
+            TokenFormat::V4Public => {
+                let seed = base64::decode(value.trim()).map_err(|_| {
+                    Error::ConfigError(format!("PASETO key {} is not valid base64", id))
+                })?;
+                if seed.len() != SEED_LENGTH {
+                    return Err(Error::ConfigError(format!(
+                        "PASETO key {} has to be a {} byte Ed25519 seed, got {} bytes",
+                        id,
+                        SEED_LENGTH,
+                        seed.len()
+                    )));
+                }
+                Ed25519KeyPair::from_seed_unchecked(&seed)
+                    .map(Key::Public)
+                    .map_err(|e| Error::ConfigError(format!("PASETO key {}: {}", id, e)))
+            }
+        }
+    }
+
+    /// Returns the claims of the token, without validating them. v2 has no
+    /// implicit assertions, `v2.local` tokens cannot be read by others anyway.
+    fn open(&self, token: &str, footer: Option<&str>, implicit: &str) -> Result<String, Error> {
+        match self {
+            Key::Local(key) => paseto::v2::decrypt_paseto(token, footer, key)
+                .map_err(|_| Error::CannotDecryptToken),
+            Key::Public(key_pair) => verify_v4_public(
+                token,
+                footer.unwrap_or(""),
+                implicit,
+                key_pair.public_key().as_ref(),
+            ),
+        }
+    }
+}
+
+struct Keys {
+    current: String,
+    keys: HashMap<String, Key>,
+}
+
+/// PASETO keys of the service. New tokens are issued with the current key,
+/// the previous keys only validate tokens issued before a rotation, so nobody
+/// is logged out when a new key is introduced.
+#[derive(Clone)]
+pub struct KeyRing {
+    keys: Arc<RwLock<Keys>>,
+    format: TokenFormat,
+    file: Option<String>,
+}
+
+impl KeyRing {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let format = config.token_format.parse::<TokenFormat>()?;
+        let file = config.paseto_keys_file.clone();
+        let keys = load(format, file.as_deref())?;
+
+        event!(
+            Level::INFO,
+            format = config.token_format.as_str(),
+            current = keys.current.as_str(),
+            keys = keys.keys.len(),
+            "PASETO keys loaded"
+        );
+
+        Ok(KeyRing {
+            keys: Arc::new(RwLock::new(keys)),
+            format,
+            file,
+        })
+    }
+
+    /// Re-reads the keys. If the new keys are invalid, the old ones stay in use.
+    pub fn reload(&self) -> Result<(), Error> {
+        let keys = load(self.format, self.file.as_deref())?;
+
+        event!(
+            Level::INFO,
+            current = keys.current.as_str(),
+            keys = keys.keys.len(),
+            "PASETO keys reloaded"
+        );
+
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = keys;
+        Ok(())
+    }
+
+    /// Encrypts or signs the claims of a session with the current key
+    pub fn issue(&self, claims: &serde_json::Value) -> String {
+        self.seal(claims, "")
+    }
+
+    /// Issues a token for use within this service only, like email verification
+    /// links. The purpose is added as claim and, for `v4.public`, as implicit
+    /// assertion, so other services verifying our sessions with the public key
+    /// reject these tokens.
+    pub fn issue_for(&self, purpose: &str, claims: &serde_json::Value) -> String {
+        let mut claims = claims.clone();
+        claims["purpose"] = serde_json::json!(purpose);
+        self.seal(&claims, purpose)
+    }
+
+    fn seal(&self, claims: &serde_json::Value, implicit: &str) -> String {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        let footer = serde_json::to_string(&Footer {
+            kid: keys.current.clone(),
+        })
+        .expect("footer is always serializable");
+        let message = claims.to_string();
+
+        match &keys.keys[&keys.current] {
+            Key::Local(key) => paseto::v2::local_paseto(&message, Some(&footer), key)
+                .expect("Failed to construct paseto token"),
+            Key::Public(key_pair) => sign_v4_public(&message, &footer, implicit, key_pair),
+        }
+    }
+
+    /// Validates a session token, tokens issued for a purpose are rejected
+    pub fn validate(&self, token: &str) -> Result<serde_json::Value, Error> {
+        let claims = self.open(token, "")?;
+        if claims.get("purpose").is_some() {
+            return Err(Error::CannotDecryptToken);
+        }
+        Ok(claims)
+    }
+
+    /// Validates a token from `issue_for` with the same purpose
+    pub fn validate_for(&self, purpose: &str, token: &str) -> Result<serde_json::Value, Error> {
+        let claims = self.open(token, purpose)?;
+        if claims.get("purpose") != Some(&serde_json::json!(purpose)) {
+            return Err(Error::CannotDecryptToken);
+        }
+        Ok(claims)
+    }
+
+    /// Decrypts or verifies the token with the key named in its footer and validates its expiry
+    fn open(&self, token: &str, implicit: &str) -> Result<serde_json::Value, Error> {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+
+        let message = match token.split('.').nth(3) {
+            Some(footer) => {
+                let footer = base64::decode_config(footer, base64::URL_SAFE_NO_PAD)
+                    .ok()
+                    .and_then(|footer| String::from_utf8(footer).ok())
+                    .ok_or(Error::CannotDecryptToken)?;
+                let kid = serde_json::from_str::<Footer>(&footer)
+                    .map_err(|_| Error::CannotDecryptToken)?
+                    .kid;
+                let key = keys.keys.get(&kid).ok_or(Error::CannotDecryptToken)?;
+
+                key.open(token, Some(&footer), implicit)?
+            }
+            // Tokens issued before key ids were introduced, which
+            // were all encrypted with the key from `PASETO_KEY`
+            None => keys
+                .keys
+                .values()
+                .find_map(|key| key.open(token, None, implicit).ok())
+                .ok_or(Error::CannotDecryptToken)?,
+        };
+
+        paseto::tokens::validate_potential_json_blob(&message, &paseto::tokens::TimeBackend::Chrono)
+            .map_err(|_| Error::CannotDecryptToken)
+    }
+
+    /// Keys other services need to verify our tokens, empty for `v2.local`
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+
+        let mut public_keys = keys
+            .keys
+            .iter()
+            .filter_map(|(kid, key)| match key {
+                Key::Public(key_pair) => Some(PublicKey {
+                    kid: kid.clone(),
+                    key: format!(
+                        "k4.public.{}",
+                        base64::encode_config(key_pair.public_key(), base64::URL_SAFE_NO_PAD)
+                    ),
+                }),
+                Key::Local(_) => None,
+            })
+            .collect::<Vec<_>>();
+        // The current key first, clients which only look at one key pick the right one
+        public_keys.sort_by_key(|key| (key.kid != keys.current, key.kid.clone()));
+
+        public_keys
+    }
+
+    #[cfg(test)]
+    pub fn from_keys(
+        format: TokenFormat,
+        current: &str,
+        keys: &[(&str, &str)],
+    ) -> Result<Self, Error> {
+        let keys = Keys {
+            current: current.to_string(),
+            keys: keys
+                .iter()
+                .map(|(id, key)| Ok((id.to_string(), Key::parse(format, id, key.to_string())?)))
+                .collect::<Result<_, Error>>()?,
+        };
+        validate_keys(&keys)?;
+
+        Ok(KeyRing {
+            keys: Arc::new(RwLock::new(keys)),
+            format,
+            file: None,
+        })
+    }
+}
+
+fn load(format: TokenFormat, file: Option<&str>) -> Result<Keys, Error> {
+    let (current, values) = match file {
+        Some(path) => {
+            let content = fs::read_to_string(path).map_err(Error::IoError)?;
+            let file = serde_json::from_str::<KeysFile>(&content)
+                .map_err(|e| Error::ConfigError(format!("Cannot parse {}: {}", path, e)))?;
+            (file.current, file.keys)
+        }
+        None => {
+            let key = env::var("PASETO_KEY")
+                .map_err(|_| Error::ConfigError("PASETO_KEY not set".to_string()))?;
+            (
+                ENV_KEY_ID.to_string(),
+                HashMap::from([(ENV_KEY_ID.to_string(), key)]),
+            )
+        }
+    };
+
+    let keys = Keys {
+        current,
+        keys: values
+            .into_iter()
+            .map(|(id, value)| {
+                let key = Key::parse(format, &id, value)?;
+                Ok((id, key))
+            })
+            .collect::<Result<_, Error>>()?,
+    };
+
+    validate_keys(&keys)?;
+    Ok(keys)
+}
+
+fn validate_keys(keys: &Keys) -> Result<(), Error> {
+    if !keys.keys.contains_key(&keys.current) {
+        return Err(Error::ConfigError(format!(
+            "Current PASETO key {} not found",
+            keys.current
+        )));
+    }
+
+    Ok(())
+}
+
+/// Signs the message as described in the PASETO v4.public spec. Sessions
+/// have no implicit assertion, so other services can verify them.
+fn sign_v4_public(
+    message: &str,
+    footer: &str,
+    implicit: &str,
+    key_pair: &Ed25519KeyPair,
+) -> String {
+    let pre_auth = pae(&[
+        V4_PUBLIC_HEADER.as_bytes(),
+        message.as_bytes(),
+        footer.as_bytes(),
+        implicit.as_bytes(),
+    ]);
+    let signature = key_pair.sign(&pre_auth);
+
+    let mut payload = message.as_bytes().to_vec();
+    payload.extend_from_slice(signature.as_ref());
+
+    let mut token = format!(
+        "{}{}",
+        V4_PUBLIC_HEADER,
+        base64::encode_config(&payload, base64::URL_SAFE_NO_PAD)
+    );
+    if !footer.is_empty() {
+        token.push('.');
+        token.push_str(&base64::encode_config(footer, base64::URL_SAFE_NO_PAD));
+    }
+
+    token
+}
+
+fn verify_v4_public(
+    token: &str,
+    footer: &str,
+    implicit: &str,
+    public_key: &[u8],
+) -> Result<String, Error> {
+    let payload = token
+        .strip_prefix(V4_PUBLIC_HEADER)
+        .and_then(|rest| rest.split('.').next())
+        .and_then(|payload| base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok())
+        .filter(|payload| payload.len() > SIGNATURE_LENGTH)
+        .ok_or(Error::CannotDecryptToken)?;
+    let (message, signature) = payload.split_at(payload.len() - SIGNATURE_LENGTH);
+
+    let pre_auth = pae(&[
+        V4_PUBLIC_HEADER.as_bytes(),
+        message,
+        footer.as_bytes(),
+        implicit.as_bytes(),
+    ]);
+    signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(&pre_auth, signature)
+        .map_err(|_| Error::CannotDecryptToken)?;
+
+    String::from_utf8(message.to_vec()).map_err(|_| Error::CannotDecryptToken)
+}
+
+/// Reloads the key ring whenever the process receives SIGHUP, so keys
+/// can be rotated by editing the keys file
+#[cfg(unix)]
+pub fn reload_on_sighup(key_ring: KeyRing) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                event!(Level::ERROR, "Cannot listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            if let Err(e) = key_ring.reload() {
+                event!(Level::ERROR, "Cannot reload PASETO keys: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_key_ring: KeyRing) {}
+
+#[cfg(test)]
+mod keys_tests {
+    use super::*;
+
+    const OLD_KEY: &str = "RANDOM WORDS WINTER MACINTOSH PC";
+    const NEW_KEY: &str = "ANOTHER THIRTY TWO BYTES LONG KY";
+    /// Seed of the official v4.public test vectors
+    const SEED: &str = "tMv7Q99M4hByfZU+SnEzB/oZu32fhQQUONnhG5QqN3Q=";
+    const OTHER_SEED: &str = "ERERERERERERERERERERERERERERERERERERERERERE=";
+
+    fn claims() -> serde_json::Value {
+        serde_json::json!({
+            "exp": (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339(),
+            "account_id": 3,
+        })
+    }
+
+    #[test]
+    fn tokens_of_previous_keys_stay_valid() {
+        let old = KeyRing::from_keys(TokenFormat::V2Local, "old", &[("old", OLD_KEY)]).unwrap();
+        let token = old.issue(&claims());
+
+        let rotated = KeyRing::from_keys(
+            TokenFormat::V2Local,
+            "new",
+            &[("new", NEW_KEY), ("old", OLD_KEY)],
+        )
+        .unwrap();
+        assert_eq!(
+            rotated.validate(&token).unwrap()["account_id"],
+            serde_json::json!(3)
+        );
+        assert!(rotated.validate(&rotated.issue(&claims())).is_ok());
+
+        let retired = KeyRing::from_keys(TokenFormat::V2Local, "new", &[("new", NEW_KEY)]).unwrap();
+        assert!(retired.validate(&token).is_err());
+    }
+
+    #[test]
+    fn tokens_without_footer_are_tried_with_every_key() {
+        let token = paseto::tokens::PasetoBuilder::new()
+            .set_encryption_key(OLD_KEY.as_bytes())
+            .set_expiration(&(chrono::Utc::now() + chrono::Duration::days(1)))
+            .set_claim("account_id", serde_json::json!(3))
+            .build()
+            .unwrap();
+
+        let key_ring = KeyRing::from_keys(
+            TokenFormat::V2Local,
+            "new",
+            &[("new", NEW_KEY), ("old", OLD_KEY)],
+        )
+        .unwrap();
+        assert!(key_ring.validate(&token).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        assert!(
+            KeyRing::from_keys(TokenFormat::V2Local, "short", &[("short", "too short")]).is_err()
+        );
+        assert!(KeyRing::from_keys(TokenFormat::V2Local, "missing", &[("old", OLD_KEY)]).is_err());
+        assert!(KeyRing::from_keys(TokenFormat::V4Public, "old", &[("old", OLD_KEY)]).is_err());
+    }
+
+    #[test]
+    fn signs_v4_public_test_vector() {
+        let key = Key::parse(TokenFormat::V4Public, "test", SEED.to_string()).unwrap();
+        let key_pair = match &key {
+            Key::Public(key_pair) => key_pair,
+            Key::Local(_) => unreachable!(),
+        };
+        let message = r#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+
+        // Test vector 4-S-1
+        let token = sign_v4_public(message, "", "", key_pair);
+        assert_eq!(
+            token,
+            "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9bg_XBBzds8lTZShVlwwKSgeKpLT3yukTw6JUz3W4h_ExsQV-P0V54zemZDcAxFaSeef1QlXEFtkqxT1ciiQEDA"
+        );
+        assert_eq!(key.open(&token, None, "").unwrap(), message);
+    }
+
+    #[test]
+    fn public_tokens_are_verified_with_the_public_key() {
+        let key_ring = KeyRing::from_keys(
+            TokenFormat::V4Public,
+            "new",
+            &[("new", OTHER_SEED), ("old", SEED)],
+        )
+        .unwrap();
+        let token = key_ring.issue(&claims());
+
+        assert!(token.starts_with("v4.public."));
+        assert_eq!(
+            key_ring.validate(&token).unwrap()["account_id"],
+            serde_json::json!(3)
+        );
+        assert!(key_ring.validate(&token.replacen("ey", "ez", 1)).is_err());
+
+        // Purpose tokens fail verification without knowing their purpose
+        let challenge = key_ring.issue_for("challenge", &claims());
+        assert!(key_ring.validate(&challenge).is_err());
+        assert!(key_ring.validate_for("verification", &challenge).is_err());
+        assert_eq!(
+            key_ring.validate_for("challenge", &challenge).unwrap()["purpose"],
+            serde_json::json!("challenge")
+        );
+        let key = Key::parse(TokenFormat::V4Public, "new", OTHER_SEED.to_string()).unwrap();
+        let footer = challenge.split('.').nth(3).unwrap();
+        let footer = base64::decode_config(footer, base64::URL_SAFE_NO_PAD).unwrap();
+        let footer = String::from_utf8(footer).unwrap();
+        assert!(key.open(&challenge, Some(&footer), "").is_err());
+        assert!(key_ring.validate_for("challenge", &token).is_err());
+
+        let public_keys = key_ring.public_keys();
+        assert_eq!(public_keys.len(), 2);
+        assert_eq!(public_keys[0].kid, "new");
+        assert_eq!(
+            public_keys[1].key,
+            "k4.public.Hrnbu7wEfAP9cGBOAHHwmH4Wsot1ciXBHwBBXQ4gsaI"
+        );
+    }
+}
@@ -1,27 +1,84 @@
 #![warn(clippy::all)]
+// The chain of `or`ed routes nests deeper than the default limit
+#![recursion_limit = "256"]
 
 pub use handle_errors;
 
+use std::convert::Infallible;
 use tokio::sync::{oneshot, oneshot::Sender};
 use tracing_subscriber::fmt::format::FmtSpan;
-use warp::{http::Method, Filter, Reply};
+use warp::{http::Method, reply::Response, Filter};
 
+use routes::authentication::{client_info, require_role, require_scope, require_session};
+use types::{account::Role, token::Scope};
+use validation::{validated_form, validated_json};
+
+mod circuit_breaker;
 pub mod config;
-mod profanity;
+mod http_client;
+mod keys;
+mod mail;
+mod metrics;
+mod moderation;
+mod oidc;
+mod password;
+mod request_id;
 mod routes;
+mod server;
 mod store;
+mod throttle;
+mod totp;
 pub mod types;
+mod validation;
 
 pub struct OneshotHandler {
     pub sender: Sender<i32>,
 }
 
-async fn build_routes(store: store::Store) -> impl Filter<Extract = impl Reply> + Clone {
+#[allow(clippy::too_many_arguments)]
+async fn build_routes(
+    config: &config::Config,
+    store: store::Store,
+    moderator: moderation::Moderator,
+    mailer: mail::Mailer,
+    password_policy: validation::PasswordPolicy,
+    password_hasher: password::PasswordHasher,
+    key_ring: keys::KeyRing,
+    oidc_client: Option<oidc::OidcClient>,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone + Send + Sync + 'static {
+    let auth =
+        routes::authentication::auth(store.clone(), key_ring.clone(), config.session_cookies);
+    let verified = routes::authentication::verified(
+        store.clone(),
+        key_ring.clone(),
+        config.session_cookies,
+        config.require_verified_email,
+    );
     let store_filter = warp::any().map(move || store.clone());
+    let moderator_filter = warp::any().map(move || moderator.clone());
+    let mailer_filter = warp::any().map(move || mailer.clone());
+    let policy_filter = warp::any().map(move || password_policy.clone());
+    let hasher_filter = warp::any().map(move || password_hasher.clone());
+    let keys_filter = warp::any().map(move || key_ring.clone());
+    let throttle = throttle::LoginThrottle::new(config);
+    let throttle_filter = warp::any().map(move || throttle.clone());
+    let resend_interval = config.verification_resend_interval;
+    let session_cookies = config.session_cookies;
+    let totp_issuer = config.totp_issuer.clone();
+    // The OpenID Connect routes do not exist unless a provider is configured
+    let oidc_filter = warp::any().and_then(move || {
+        let oidc_client = oidc_client.clone();
+        async move { oidc_client.ok_or_else(warp::reject::not_found) }
+    });
 
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_header("content-type")
+        .allow_headers(vec![
+            "content-type",
+            "authorization",
+            "x-csrf-token",
+            "x-request-id",
+        ])
         .allow_methods(&[Method::PUT, Method::DELETE, Method::GET, Method::POST]);
 
     let get_questions = warp::get()
@@ -35,59 +92,404 @@ async fn build_routes(store: store::Store) -> impl Filter<Extract = impl Reply>
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(
+            verified
+                .clone()
+                .and_then(require_scope(Scope::QuestionsWrite)),
+        )
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(moderator_filter.clone())
+        .and(request_id::request_id())
+        .and(validated_json())
         .and_then(routes::question::update_question);
 
     let delete_question = warp::delete()
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(auth.clone().and_then(require_scope(Scope::QuestionsWrite)))
         .and(store_filter.clone())
         .and_then(routes::question::delete_question);
 
     let add_question = warp::post()
         .and(warp::path("questions"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(
+            verified
+                .clone()
+                .and_then(require_scope(Scope::QuestionsWrite)),
+        )
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(moderator_filter.clone())
+        .and(request_id::request_id())
+        .and(validated_json())
         .and_then(routes::question::add_question);
 
     let add_answer = warp::post()
         .and(warp::path("answers"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(
+            verified
+                .clone()
+                .and_then(require_scope(Scope::AnswersWrite)),
+        )
         .and(store_filter.clone())
-        .and(warp::body::form())
+        .and(moderator_filter.clone())
+        .and(request_id::request_id())
+        .and(validated_form())
         .and_then(routes::answer::add_answer);
 
+    let get_answers = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("answers"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::answer::get_answers);
+
+    let update_answer = warp::put()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(
+            verified
+                .clone()
+                .and_then(require_scope(Scope::AnswersWrite)),
+        )
+        .and(store_filter.clone())
+        .and(moderator_filter.clone())
+        .and(request_id::request_id())
+        .and(validated_json())
+        .and_then(routes::answer::update_answer);
+
+    let delete_answer = warp::delete()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_scope(Scope::AnswersWrite)))
+        .and(store_filter.clone())
+        .and_then(routes::answer::delete_answer);
+
+    let get_reviews = warp::get()
+        .and(warp::path("moderation"))
+        .and(warp::path("reviews"))
+        .and(warp::path::end())
+        .and(
+            auth.clone()
+                .and_then(require_session)
+                .and_then(require_role(Role::Moderator)),
+        )
+        .and(store_filter.clone())
+        .and_then(routes::moderation::get_reviews);
+
+    let resolve_review = warp::delete()
+        .and(warp::path("moderation"))
+        .and(warp::path("reviews"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(
+            auth.clone()
+                .and_then(require_session)
+                .and_then(require_role(Role::Moderator)),
+        )
+        .and(store_filter.clone())
+        .and_then(routes::moderation::resolve_review);
+
+    let health = warp::get()
+        .and(warp::path("health"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(moderator_filter.clone())
+        .and_then(routes::health::health);
+
+    let metrics = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and(moderator_filter.clone())
+        .and_then(routes::health::metrics);
+
+    let get_profile = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::account::get_profile);
+
+    let update_profile = warp::put()
+        .and(warp::path("me"))
+        .and(warp::path("profile"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(validated_json())
+        .and_then(routes::account::update_profile);
+
+    let export_account = warp::get()
+        .and(warp::path("me"))
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and_then(routes::account::export_account);
+
+    let delete_account = warp::delete()
+        .and(warp::path("me"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(hasher_filter.clone())
+        .and(validated_json())
+        .and_then(routes::account::delete_account);
+
+    let update_role = warp::put()
+        .and(warp::path("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("role"))
+        .and(warp::path::end())
+        .and(
+            auth.clone()
+                .and_then(require_session)
+                .and_then(require_role(Role::Admin)),
+        )
+        .and(store_filter.clone())
+        .and(validated_json())
+        .and_then(routes::account::update_role);
+
+    let create_token = warp::post()
+        .and(warp::path("me"))
+        .and(warp::path("tokens"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(validated_json())
+        .and_then(routes::token::create_token);
+
+    let get_tokens = warp::get()
+        .and(warp::path("me"))
+        .and(warp::path("tokens"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and_then(routes::token::get_tokens);
+
+    let revoke_token = warp::delete()
+        .and(warp::path("me"))
+        .and(warp::path("tokens"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and_then(routes::token::revoke_token);
+
+    let get_sessions = warp::get()
+        .and(warp::path("me"))
+        .and(warp::path("sessions"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and_then(routes::session::get_sessions);
+
+    let delete_session = warp::delete()
+        .and(warp::path("me"))
+        .and(warp::path("sessions"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and_then(routes::session::delete_session);
+
     let registration = warp::post()
         .and(warp::path("registration"))
         .and(warp::path::end())
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(mailer_filter.clone())
+        .and(policy_filter.clone())
+        .and(hasher_filter.clone())
+        .and(keys_filter.clone())
+        .and(validated_json())
         .and_then(routes::authentication::register);
 
-    let login = warp::post()
+    let verify_email = warp::get()
+        .and(warp::path("verify-email"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and(keys_filter.clone())
+        .and_then(routes::authentication::verify_email);
+
+    let resend_verification = warp::post()
+        .and(warp::path("verify-email"))
+        .and(warp::path("resend"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(mailer_filter.clone())
+        .and(keys_filter.clone())
+        .and(warp::any().map(move || resend_interval))
+        .and_then(routes::authentication::resend_verification);
+
+    let change_password = warp::put()
+        .and(warp::path("me"))
+        .and(warp::path("password"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(policy_filter.clone())
+        .and(hasher_filter.clone())
+        .and(keys_filter.clone())
+        .and(warp::any().map(move || session_cookies))
+        .and(client_info())
+        .and(validated_json())
+        .and_then(routes::authentication::change_password);
+
+    let change_email = warp::put()
+        .and(warp::path("me"))
+        .and(warp::path("email"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(mailer_filter.clone())
+        .and(hasher_filter.clone())
+        .and(keys_filter.clone())
+        .and(validated_json())
+        .and_then(routes::authentication::change_email);
+
+    let public_keys = warp::get()
+        .and(warp::path(".well-known"))
+        .and(warp::path("paseto-keys"))
+        .and(warp::path::end())
+        .and(keys_filter.clone())
+        .and_then(routes::authentication::public_keys);
+
+    let oidc_start = warp::get()
+        .and(warp::path("auth"))
+        .and(warp::path("oidc"))
+        .and(warp::path("start"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(oidc_filter.clone())
+        .and(keys_filter.clone())
+        .and_then(routes::oidc::start);
+
+    let oidc_callback = warp::get()
+        .and(warp::path("auth"))
+        .and(warp::path("oidc"))
+        .and(warp::path("callback"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(warp::cookie::optional::<String>("oidc_state"))
+        .and(store_filter.clone())
+        .and(oidc_filter.clone())
+        .and(keys_filter.clone())
+        .and(warp::any().map(move || session_cookies))
+        .and(client_info())
+        .and_then(routes::oidc::callback);
+
+    let two_factor_setup = warp::post()
+        .and(warp::path("me"))
+        .and(warp::path("2fa"))
+        .and(warp::path("setup"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(warp::any().map(move || totp_issuer.clone()))
+        .and_then(routes::two_factor::setup);
+
+    let two_factor_confirm = warp::post()
+        .and(warp::path("me"))
+        .and(warp::path("2fa"))
+        .and(warp::path("confirm"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(validated_json())
+        .and_then(routes::two_factor::confirm);
+
+    let two_factor_disable = warp::delete()
+        .and(warp::path("me"))
+        .and(warp::path("2fa"))
+        .and(warp::path::end())
+        .and(auth.clone().and_then(require_session))
+        .and(store_filter.clone())
+        .and(hasher_filter.clone())
+        .and(validated_json())
+        .and_then(routes::two_factor::disable);
+
+    let two_factor_login = warp::post()
         .and(warp::path("login"))
+        .and(warp::path("2fa"))
         .and(warp::path::end())
         .and(store_filter.clone())
+        .and(throttle_filter.clone())
+        .and(keys_filter.clone())
+        .and(warp::any().map(move || session_cookies))
+        .and(client_info())
         .and(warp::body::json())
+        .and_then(routes::two_factor::login);
+
+    let login = warp::post()
+        .and(warp::path("login"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(throttle_filter.clone())
+        .and(hasher_filter.clone())
+        .and(keys_filter.clone())
+        .and(warp::any().map(move || session_cookies))
+        .and(client_info())
+        .and(validated_json())
         .and_then(routes::authentication::login);
 
-    get_questions
+    // Boxing the groups keeps the nested filter types, and the stack
+    // needed to poll them in debug builds, small
+    let content_routes = get_questions
         .or(update_question)
         .or(add_question)
         .or(delete_question)
         .or(add_answer)
-        .or(registration)
+        .or(get_answers)
+        .or(update_answer)
+        .or(delete_answer)
+        .or(get_reviews)
+        .or(resolve_review)
+        .boxed();
+
+    let account_routes = get_profile
+        .or(update_profile)
+        .or(export_account)
+        .or(delete_account)
+        .or(update_role)
+        .or(create_token)
+        .or(get_tokens)
+        .or(revoke_token)
+        .or(get_sessions)
+        .or(delete_session)
+        .boxed();
+
+    let auth_routes = registration
+        .or(verify_email)
+        .or(resend_verification)
+        .or(change_password)
+        .or(change_email)
         .or(login)
+        .or(two_factor_login)
+        .or(two_factor_setup)
+        .or(two_factor_confirm)
+        .or(two_factor_disable)
+        .or(public_keys)
+        .or(oidc_start)
+        .or(oidc_callback)
+        .boxed();
+
+    let service_routes = health.or(metrics).boxed();
+
+    let routes = content_routes
+        .or(account_routes)
+        .or(auth_routes)
+        .or(service_routes)
         .with(cors)
-        .with(warp::trace::request())
-        .recover(handle_errors::return_error)
+        .with(request_id::trace_request());
+
+    handle_errors::recover_problems(routes, request_id::request_id())
 }
 
 pub async fn setup_store(config: &config::Config) -> Result<store::Store, handle_errors::Error> {
@@ -103,8 +505,9 @@ pub async fn setup_store(config: &config::Config) -> Result<store::Store, handle
         .await
         .map_err(handle_errors::Error::MigrationError)?;
 
+    // Audit events (e.g. login lockouts) are always recorded
     let log_filter = format!(
-        "handle_errors={},rust_web_dev={},warp={}",
+        "handle_errors={},rust_web_dev={},warp={},audit=info",
         config.log_level, config.log_level, config.log_level
     );
 
@@ -119,13 +522,57 @@ pub async fn setup_store(config: &config::Config) -> Result<store::Store, handle
     Ok(store)
 }
 
-pub async fn run(config: config::Config, store: store::Store) {
-    let routes = build_routes(store).await;
-    warp::serve(routes).run(([0, 0, 0, 0], config.port)).await;
+pub async fn run(config: config::Config, store: store::Store) -> Result<(), handle_errors::Error> {
+    let http_client = http_client::HttpClient::new(&config)?;
+    let moderator = moderation::Moderator::new(&config, http_client, store.clone())?;
+    moderation::ModerationCache::delete_expired_periodically(&config, store.clone());
+    let mailer = mail::Mailer::new(&config)?;
+    let password_policy = validation::PasswordPolicy::new(&config)?;
+    let password_hasher = password::PasswordHasher::new(&config)?;
+    let key_ring = keys::KeyRing::new(&config)?;
+    keys::reload_on_sighup(key_ring.clone());
+    let oidc_client = oidc::OidcClient::new(&config)?;
+    let routes = build_routes(
+        &config,
+        store,
+        moderator,
+        mailer,
+        password_policy,
+        password_hasher,
+        key_ring,
+        oidc_client,
+    )
+    .await;
+    server::serve(
+        routes,
+        ([0, 0, 0, 0], config.port).into(),
+        std::future::pending(),
+    )
+    .await
 }
 
-pub async fn oneshot(store: store::Store) -> OneshotHandler {
-    let routes = build_routes(store).await;
+pub async fn oneshot(
+    config: &config::Config,
+    store: store::Store,
+) -> Result<OneshotHandler, handle_errors::Error> {
+    let http_client = http_client::HttpClient::new(config)?;
+    let moderator = moderation::Moderator::new(config, http_client, store.clone())?;
+    let mailer = mail::Mailer::new(config)?;
+    let password_policy = validation::PasswordPolicy::new(config)?;
+    let password_hasher = password::PasswordHasher::new(config)?;
+    let key_ring = keys::KeyRing::new(config)?;
+    let oidc_client = oidc::OidcClient::new(config)?;
+    let routes = build_routes(
+        config,
+        store,
+        moderator,
+        mailer,
+        password_policy,
+        password_hasher,
+        key_ring,
+        oidc_client,
+    )
+    .await;
     let (tx, rx) = oneshot::channel::<i32>();
 
     let socket: std::net::SocketAddr = "127.0.0.1:3030"
@@ -133,11 +580,9 @@ pub async fn oneshot(store: store::Store) -> OneshotHandler {
         .parse()
         .expect("Not a valid address");
 
-    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(socket, async {
+    tokio::task::spawn(server::serve(routes, socket, async {
         rx.await.ok();
-    });
-
-    tokio::task::spawn(server);
+    }));
 
-    OneshotHandler { sender: tx }
+    Ok(OneshotHandler { sender: tx })
 }
\ No newline at end of file
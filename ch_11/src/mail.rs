@@ -0,0 +1,91 @@
+use std::env;
+
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use handle_errors::Error;
+
+use crate::config::Config;
+
+/// Sends the emails of the service (e.g. verification links).
+/// Without a configured SMTP host, emails are only logged, which
+/// is handy during local development.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Mailbox,
+    public_url: String,
+}
+
+impl Mailer {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let from = config
+            .mail_from
+            .parse::<Mailbox>()
+            .map_err(|e| Error::MailError(Box::new(e)))?;
+
+        let transport = match &config.smtp_host {
+            Some(host) => {
+                let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                    .map_err(|e| Error::MailError(Box::new(e)))?;
+
+                if let (Ok(user), Ok(password)) =
+                    (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+                {
+                    builder = builder.credentials(Credentials::new(user, password));
+                }
+
+                Some(builder.build())
+            }
+            None => None,
+        };
+
+        Ok(Mailer {
+            transport,
+            from,
+            public_url: config.public_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), Error> {
+        let transport = match &self.transport {
+            Some(transport) => transport,
+            None => {
+                tracing::event!(tracing::Level::INFO, to, subject, "{}", body);
+                return Ok(());
+            }
+        };
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to
+                .parse::<Mailbox>()
+                .map_err(|e| Error::MailError(Box::new(e)))?)
+            .subject(subject)
+            .body(body)
+            .map_err(|e| Error::MailError(Box::new(e)))?;
+
+        transport
+            .send(message)
+            .await
+            .map_err(|e| Error::MailError(Box::new(e)))?;
+
+        Ok(())
+    }
+
+    pub async fn send_verification(&self, to: &str, token: &str) -> Result<(), Error> {
+        let link = format!("{}/verify-email?token={}", self.public_url, token);
+
+        self.send(
+            to,
+            "Please verify your email address",
+            format!(
+                "Welcome!\n\nPlease confirm your email address by opening the link below:\n\n{}\n\nThe link is valid for 24 hours.",
+                link
+            ),
+        )
+        .await
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::Write;
+
+/// Collects metrics in the Prometheus text exposition format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Adds a metric with a single, unlabeled sample. `kind` is `counter` or `gauge`.
+    pub fn single(&mut self, name: &str, kind: &str, help: &str, value: u64) {
+        self.header(name, kind, help);
+        let _ = writeln!(self.text, "{} {}", name, value);
+    }
+
+    /// Adds a metric with a sample per value of `label`
+    pub fn labeled(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        label: &str,
+        samples: &[(&str, u64)],
+    ) {
+        self.header(name, kind, help);
+        for (value, sample) in samples {
+            let _ = writeln!(self.text, "{}{{{}=\"{}\"}} {}", name, label, value, sample);
+        }
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+
+    #[test]
+    fn renders_text_format() {
+        let mut metrics = Metrics::new();
+        metrics.single("open", "gauge", "Whether it is open", 1);
+        metrics.labeled(
+            "calls_total",
+            "counter",
+            "Calls by outcome",
+            "outcome",
+            &[("success", 3), ("failure", 0)],
+        );
+
+        assert_eq!(
+            metrics.into_text(),
+            "# HELP open Whether it is open\n\
+             # TYPE open gauge\n\
+             open 1\n\
+             # HELP calls_total Calls by outcome\n\
+             # TYPE calls_total counter\n\
+             calls_total{outcome=\"success\"} 3\n\
+             calls_total{outcome=\"failure\"} 0\n"
+        );
+    }
+}
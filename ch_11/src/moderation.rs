@@ -0,0 +1,357 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use handle_errors::Error;
+
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+use crate::config::Config;
+use crate::http_client::HttpClient;
+use crate::metrics::Metrics;
+use crate::store::Store;
+use crate::types::moderation::ModerationHealth;
+
+mod api_layer;
+mod cache;
+mod word_list;
+
+pub use api_layer::ApiLayerModerator;
+pub use cache::ModerationCache;
+pub use word_list::WordListModerator;
+
+/// Censors offensive words in questions and answers before they are stored
+#[async_trait]
+pub trait ContentModerator: Send + Sync {
+    /// Returns the content with offensive words replaced by `*`. The request id
+    /// is passed on to external services, so calls can be found in their logs.
+    async fn censor(&self, content: String, request_id: &str) -> Result<String, Error>;
+}
+
+/// Content ready to be stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Moderated {
+    pub content: String,
+    /// Stored uncensored as the moderator failed, a moderator has to look at it
+    pub needs_review: bool,
+}
+
+/// What happens to content while the moderator fails
+enum FailurePolicy {
+    /// Rejects the content, the client may try again later
+    Closed,
+    /// Accepts the content as it is and queues it for review
+    Open,
+    /// Censors the content with a local word list instead
+    WordList(WordListModerator),
+}
+
+#[derive(Debug, Default)]
+struct ModerationStats {
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    short_circuited: AtomicU64,
+    fallbacks: AtomicU64,
+}
+
+/// The moderator selected with `--content-moderator`, shared by all routes.
+/// Results are cached, and calls go through a circuit breaker, so a failing
+/// moderation service is not waited for on every request.
+#[derive(Clone)]
+pub struct Moderator {
+    provider: Arc<dyn ContentModerator>,
+    provider_name: String,
+    policy: Arc<FailurePolicy>,
+    policy_name: String,
+    breaker: Arc<CircuitBreaker>,
+    cache: Arc<ModerationCache>,
+    stats: Arc<ModerationStats>,
+}
+
+impl Moderator {
+    pub fn new(config: &Config, http_client: HttpClient, store: Store) -> Result<Self, Error> {
+        let provider: Arc<dyn ContentModerator> = match config.content_moderator.as_str() {
+            "apilayer" => Arc::new(ApiLayerModerator::from_env(http_client)?),
+            "word-list" => Arc::new(WordListModerator::new(config)?),
+            "none" => Arc::new(NoopModerator),
+            other => {
+                return Err(Error::ConfigError(format!(
+                    "Unknown content moderator {}, expected apilayer, word-list or none",
+                    other
+                )))
+            }
+        };
+        let policy = match config.moderation_failure_policy.as_str() {
+            "fail-closed" => FailurePolicy::Closed,
+            "fail-open" => FailurePolicy::Open,
+            "word-list" => FailurePolicy::WordList(WordListModerator::new(config)?),
+            other => {
+                return Err(Error::ConfigError(format!(
+                    "Unknown moderation failure policy {}, expected fail-closed, fail-open or word-list",
+                    other
+                )))
+            }
+        };
+        if config.moderation_breaker_failures == 0 {
+            return Err(Error::ConfigError(
+                "moderation_breaker_failures must be at least 1".to_string(),
+            ));
+        }
+
+        tracing::event!(
+            tracing::Level::INFO,
+            moderator = config.content_moderator.as_str(),
+            failure_policy = config.moderation_failure_policy.as_str(),
+            "content moderation configured"
+        );
+
+        Ok(Moderator {
+            provider,
+            provider_name: config.content_moderator.clone(),
+            policy: Arc::new(policy),
+            policy_name: config.moderation_failure_policy.clone(),
+            breaker: Arc::new(CircuitBreaker::new(
+                "moderation",
+                config.moderation_breaker_failures,
+                Duration::from_secs(config.moderation_breaker_open_seconds),
+            )),
+            cache: Arc::new(ModerationCache::new(config, store)),
+            stats: Arc::new(ModerationStats::default()),
+        })
+    }
+
+    /// Censors the content, or applies the failure policy if the moderator
+    /// failed or is not called while the circuit is open
+    pub async fn censor(&self, content: String, request_id: &str) -> Result<Moderated, Error> {
+        let key = ModerationCache::key(&self.provider_name, &content);
+        if let Some(censored) = self.cache.get(&key).await {
+            return Ok(Moderated {
+                content: censored,
+                needs_review: false,
+            });
+        }
+
+        if !self.breaker.allow() {
+            self.stats.short_circuited.fetch_add(1, Ordering::Relaxed);
+            return self.fall_back(content);
+        }
+
+        match self.provider.censor(content.clone(), request_id).await {
+            Ok(censored) => {
+                self.breaker.record_success();
+                self.stats.succeeded.fetch_add(1, Ordering::Relaxed);
+                self.cache.put(&key, &censored);
+                Ok(Moderated {
+                    content: censored,
+                    needs_review: false,
+                })
+            }
+            Err(error) => {
+                self.breaker.record_failure();
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                tracing::event!(
+                    tracing::Level::ERROR,
+                    request_id,
+                    "content moderation failed: {}",
+                    error
+                );
+                self.fall_back(content)
+            }
+        }
+    }
+
+    fn fall_back(&self, content: String) -> Result<Moderated, Error> {
+        self.stats.fallbacks.fetch_add(1, Ordering::Relaxed);
+        match self.policy.as_ref() {
+            FailurePolicy::Closed => Err(Error::ModerationUnavailable(self.breaker.retry_after())),
+            FailurePolicy::Open => Ok(Moderated {
+                content,
+                needs_review: true,
+            }),
+            FailurePolicy::WordList(words) => Ok(Moderated {
+                content: words.censor_text(&content),
+                needs_review: false,
+            }),
+        }
+    }
+
+    pub fn health(&self) -> ModerationHealth {
+        ModerationHealth {
+            provider: self.provider_name.clone(),
+            failure_policy: self.policy_name.clone(),
+            circuit: self.breaker.state(),
+            consecutive_failures: self.breaker.consecutive_failures(),
+        }
+    }
+
+    pub fn write_metrics(&self, metrics: &mut Metrics) {
+        let circuit = match self.breaker.state() {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        };
+        metrics.single(
+            "moderation_circuit_state",
+            "gauge",
+            "Circuit of the moderation calls, 0 closed, 1 half-open, 2 open",
+            circuit,
+        );
+        metrics.single(
+            "moderation_circuit_opened_total",
+            "counter",
+            "Times the circuit of the moderation calls opened",
+            self.breaker.times_opened(),
+        );
+        metrics.labeled(
+            "moderation_requests_total",
+            "counter",
+            "Content passed to the moderator, by outcome",
+            "outcome",
+            &[
+                ("success", self.stats.succeeded.load(Ordering::Relaxed)),
+                ("failure", self.stats.failed.load(Ordering::Relaxed)),
+                (
+                    "short_circuited",
+                    self.stats.short_circuited.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+        metrics.single(
+            "moderation_fallbacks_total",
+            "counter",
+            "Content handled by the failure policy instead of the moderator",
+            self.stats.fallbacks.load(Ordering::Relaxed),
+        );
+        self.cache.write_metrics(metrics);
+    }
+}
+
+/// Stores content as it is, for offline development and CI
+pub struct NoopModerator;
+
+#[async_trait]
+impl ContentModerator for NoopModerator {
+    async fn censor(&self, content: String, _request_id: &str) -> Result<String, Error> {
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod moderation_tests {
+    use super::*;
+    use clap::Parser;
+
+    /// Fails every call, like an unreachable moderation service
+    struct FailingModerator;
+
+    #[async_trait]
+    impl ContentModerator for FailingModerator {
+        async fn censor(&self, _content: String, _request_id: &str) -> Result<String, Error> {
+            Err(Error::IoError(std::io::Error::other("unreachable")))
+        }
+    }
+
+    /// Counts its calls, the content is returned as it is
+    #[derive(Default)]
+    struct CountingModerator(AtomicU64);
+
+    #[async_trait]
+    impl ContentModerator for CountingModerator {
+        async fn censor(&self, content: String, _request_id: &str) -> Result<String, Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(content)
+        }
+    }
+
+    fn moderator(policy: FailurePolicy) -> Moderator {
+        moderator_with(Arc::new(FailingModerator), policy)
+    }
+
+    fn moderator_with(provider: Arc<dyn ContentModerator>, policy: FailurePolicy) -> Moderator {
+        Moderator {
+            provider,
+            provider_name: "failing".to_string(),
+            policy: Arc::new(policy),
+            policy_name: "test".to_string(),
+            breaker: Arc::new(CircuitBreaker::new("test", 2, Duration::from_secs(30))),
+            cache: Arc::new(ModerationCache::in_memory(10, Duration::from_secs(60))),
+            stats: Arc::new(ModerationStats::default()),
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_failure_policy() {
+        let closed = moderator(FailurePolicy::Closed);
+        for _ in 0..3 {
+            assert!(matches!(
+                closed.censor("shit".to_string(), "id").await,
+                Err(Error::ModerationUnavailable(_))
+            ));
+        }
+        assert_eq!(closed.health().circuit, CircuitState::Open);
+        assert_eq!(closed.stats.failed.load(Ordering::Relaxed), 2);
+        assert_eq!(closed.stats.short_circuited.load(Ordering::Relaxed), 1);
+
+        let open = moderator(FailurePolicy::Open);
+        assert_eq!(
+            open.censor("shit".to_string(), "id").await.unwrap(),
+            Moderated {
+                content: "shit".to_string(),
+                needs_review: true
+            }
+        );
+
+        let word_list = moderator(FailurePolicy::WordList(WordListModerator::from_words([
+            "shit",
+        ])));
+        assert_eq!(
+            word_list.censor("shit".to_string(), "id").await.unwrap(),
+            Moderated {
+                content: "****".to_string(),
+                needs_review: false
+            }
+        );
+
+        let mut metrics = Metrics::new();
+        closed.write_metrics(&mut metrics);
+        let text = metrics.into_text();
+        assert!(text.contains("moderation_circuit_state 2\n"));
+        assert!(text.contains("moderation_requests_total{outcome=\"short_circuited\"} 1\n"));
+
+        let config = Config::parse_from([
+            "test",
+            "--content-moderator",
+            "none",
+            "--moderation-failure-policy",
+            "fail-sideways",
+        ]);
+        let http_client = HttpClient::new(&config).unwrap();
+        let store = Store {
+            connection: sqlx::postgres::PgPoolOptions::new()
+                .connect_lazy("postgres://localhost/test")
+                .unwrap(),
+        };
+        assert!(Moderator::new(&config, http_client, store).is_err());
+    }
+
+    #[tokio::test]
+    async fn caches_results() {
+        let provider = Arc::new(CountingModerator::default());
+        let moderator = moderator_with(provider.clone(), FailurePolicy::Closed);
+
+        for _ in 0..3 {
+            let moderated = moderator.censor("same".to_string(), "id").await.unwrap();
+            assert_eq!(moderated.content, "same");
+        }
+        moderator.censor("other".to_string(), "id").await.unwrap();
+        assert_eq!(provider.0.load(Ordering::Relaxed), 2);
+
+        let mut metrics = Metrics::new();
+        moderator.write_metrics(&mut metrics);
+        let text = metrics.into_text();
+        assert!(text.contains("moderation_cache_requests_total{result=\"hit\"} 2\n"));
+        assert!(text.contains("moderation_cache_requests_total{result=\"miss\"} 2\n"));
+    }
+}
@@ -0,0 +1,156 @@
+use std::env;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::http_client::HttpClient;
+use crate::moderation::ContentModerator;
+use crate::request_id::REQUEST_ID_HEADER;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct APIResponse {
+    message: String
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct BadWord {
+    original: String,
+    word: String,
+    deviations: i64,
+    info: i64,
+    #[serde(rename = "replacedLen")]
+    replaced_len: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct BadWordsResponse {
+    content: String,
+    bad_words_total: i64,
+    bad_words_list: Vec<BadWord>,
+    censored_content: String,
+}
+
+/// Censors content with the bad words API of APILayer
+#[derive(Clone)]
+pub struct ApiLayerModerator {
+    url: String,
+    api_key: String,
+    client: HttpClient,
+}
+
+impl ApiLayerModerator {
+    /// Reads the `API_LAYER_URL` and the `BAD_WORDS_API_KEY` secret
+    pub fn from_env(client: HttpClient) -> Result<Self, handle_errors::Error> {
+        let api_key = env::var("BAD_WORDS_API_KEY").map_err(|_| {
+            handle_errors::Error::ConfigError("BAD_WORDS_API_KEY not set".to_string())
+        })?;
+        let url = env::var("API_LAYER_URL").map_err(|_| {
+            handle_errors::Error::ConfigError("API_LAYER_URL not set".to_string())
+        })?;
+
+        Ok(ApiLayerModerator {
+            url,
+            api_key,
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl ContentModerator for ApiLayerModerator {
+    /// Sends the id of the request along, so the call can be found in the logs of the API
+    async fn censor(
+        &self,
+        content: String,
+        request_id: &str,
+    ) -> Result<String, handle_errors::Error> {
+        // Held until the response is read, so slow responses count against the limit
+        let _slot = self.client.slot().await?;
+
+        let res = self
+            .client
+            .post(&format!(
+                "{}/bad_words?censor_character=*",
+                self.url
+            ))
+            .header("apikey", &self.api_key)
+            .header(REQUEST_ID_HEADER, request_id)
+            .body(content)
+            .send()
+            .await
+            .map_err(handle_errors::Error::MiddlewareReqwestAPIError)?;
+
+        if !res.status().is_success() {
+            if res.status().is_client_error() {
+                let err = transform_error(res).await;
+                return Err(handle_errors::Error::ClientError(err));
+            } else {
+                let err = transform_error(res).await;
+                return Err(handle_errors::Error::ServerError(err));
+            }
+        }
+
+        match res.json::<BadWordsResponse>()
+            .await {
+                Ok(res) => Ok(res.censored_content),
+                Err(e) => Err(handle_errors::Error::ReqwestAPIError(e)),
+            }
+    }
+}
+
+async fn transform_error(res: reqwest::Response) -> handle_errors::APILayerError {
+    let status = res.status().as_u16();
+    // Proxies in front of the API answer with their own error pages
+    let message = match res.json::<APIResponse>().await {
+        Ok(body) => body.message,
+        Err(e) => format!("Unreadable error response: {}", e),
+    };
+
+    handle_errors::APILayerError { status, message }
+}
+
+#[cfg(test)]
+mod api_layer_tests {
+    use super::{env, ApiLayerModerator, ContentModerator, HttpClient};
+    use crate::config::Config;
+
+    use clap::Parser;
+    use mock_server::{MockServer, OneshotHandler};
+
+    #[tokio::test]
+    async fn run() {
+        let handler = run_mock();
+        censor_profane_words().await;
+        no_profane_words().await;
+        let _ = handler.sender.send(1);
+    }
+
+    fn run_mock() -> OneshotHandler {
+        env::set_var("API_LAYER_URL", "http://127.0.0.1:3030");
+        env::set_var("BAD_WORDS_API_KEY", "YES");
+
+        let socket = "127.0.0.1:3030"
+            .to_string()
+            .parse()
+            .expect("Not a valid address");
+        let mock = MockServer::new(socket);
+
+        mock.oneshot()
+    }
+
+    fn moderator() -> ApiLayerModerator {
+        let config = Config::parse_from(["test"]);
+        ApiLayerModerator::from_env(HttpClient::new(&config).unwrap()).unwrap()
+    }
+
+    async fn censor_profane_words() {
+        let content = "This is a shitty sentence".to_string();
+        let censored_content = moderator().censor(content, "test").await;
+        assert_eq!(censored_content.unwrap(), "this is a ****** sentence");
+    }
+
+    async fn no_profane_words() {
+        let content = "this is a sentence".to_string();
+        let censored_content = moderator().censor(content, "test").await;
+        assert_eq!(censored_content.unwrap(), "");
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::{event, Level};
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::store::Store;
+
+#[derive(Debug)]
+struct Entry {
+    censored: String,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    /// Keys by insertion. Entries loaded from the database may expire before
+    /// older ones, they are ignored by lookups until they are evicted.
+    order: VecDeque<(String, Instant)>,
+}
+
+/// Censored content by hash of the moderator and the original content, so the
+/// same title or answer is not sent to the moderator again. Entries are kept
+/// in memory and, with `--moderation-cache-db`, in Postgres, where they survive
+/// restarts and are shared by all instances.
+#[derive(Debug)]
+pub struct ModerationCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+    store: Option<Store>,
+    hits: AtomicU64,
+    database_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ModerationCache {
+    pub fn new(config: &Config, store: Store) -> Self {
+        ModerationCache {
+            store: config.moderation_cache_db.then_some(store),
+            ..ModerationCache::in_memory(
+                config.moderation_cache_size,
+                Duration::from_secs(config.moderation_cache_ttl_seconds),
+            )
+        }
+    }
+
+    pub fn in_memory(capacity: usize, ttl: Duration) -> Self {
+        ModerationCache {
+            capacity,
+            ttl,
+            entries: Mutex::new(Entries::default()),
+            store: None,
+            hits: AtomicU64::new(0),
+            database_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Results differ between moderators, so the moderator is part of the key
+    pub fn key(moderator: &str, content: &str) -> String {
+        let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+        context.update(moderator.as_bytes());
+        context.update(&[0]);
+        context.update(content.as_bytes());
+        base64::encode_config(context.finish().as_ref(), base64::URL_SAFE_NO_PAD)
+    }
+
+    /// The censored content, from memory or else from the database
+    pub async fn get(&self, key: &str) -> Option<String> {
+        if let Some(censored) = self.get_in_memory(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(censored);
+        }
+
+        if let Some(store) = &self.store {
+            // The cache is an optimization, a failing database only costs a moderation call
+            if let Ok(Some((censored, remaining))) =
+                store.get_cached_moderation(key, self.ttl.as_secs()).await
+            {
+                self.database_hits.fetch_add(1, Ordering::Relaxed);
+                // The entry expires in memory when it does in the database
+                self.put_in_memory(key, &censored, Duration::from_secs(remaining).min(self.ttl));
+                return Some(censored);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// The database is written in the background, the request does not wait for it
+    pub fn put(&self, key: &str, censored: &str) {
+        self.put_in_memory(key, censored, self.ttl);
+        if let Some(store) = self.store.clone() {
+            let (key, censored) = (key.to_string(), censored.to_string());
+            tokio::spawn(async move {
+                let _ = store.cache_moderation(&key, &censored).await;
+            });
+        }
+    }
+
+    /// Deletes expired entries from the database once per lifetime of an entry
+    pub fn delete_expired_periodically(config: &Config, store: Store) {
+        if !config.moderation_cache_db {
+            return;
+        }
+        let ttl_seconds = config.moderation_cache_ttl_seconds;
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(ttl_seconds.max(60)));
+            loop {
+                interval.tick().await;
+                if let Ok(deleted) = store.delete_expired_moderations(ttl_seconds).await {
+                    event!(Level::DEBUG, deleted, "expired moderation results deleted");
+                }
+            }
+        });
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get_in_memory(&self, key: &str) -> Option<String> {
+        let now = Instant::now();
+        self.entries()
+            .by_key
+            .get(key)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.censored.clone())
+    }
+
+    fn put_in_memory(&self, key: &str, censored: &str, ttl: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let expires_at = now + ttl;
+        let mut entries = self.entries();
+
+        // Drops expired entries, and the oldest ones while the cache is full
+        while let Some((oldest, oldest_expiry)) = entries.order.front().cloned() {
+            let has_room = entries.by_key.len() < self.capacity || entries.by_key.contains_key(key);
+            if oldest_expiry > now && has_room {
+                break;
+            }
+            entries.order.pop_front();
+            // A key put again has a newer entry, which stays
+            if entries
+                .by_key
+                .get(&oldest)
+                .is_some_and(|entry| entry.expires_at == oldest_expiry)
+            {
+                entries.by_key.remove(&oldest);
+            }
+        }
+
+        entries.by_key.insert(
+            key.to_string(),
+            Entry {
+                censored: censored.to_string(),
+                expires_at,
+            },
+        );
+        entries.order.push_back((key.to_string(), expires_at));
+    }
+
+    pub fn write_metrics(&self, metrics: &mut Metrics) {
+        metrics.labeled(
+            "moderation_cache_requests_total",
+            "counter",
+            "Lookups of moderation results, by where they were found",
+            "result",
+            &[
+                ("hit", self.hits.load(Ordering::Relaxed)),
+                ("database_hit", self.database_hits.load(Ordering::Relaxed)),
+                ("miss", self.misses.load(Ordering::Relaxed)),
+            ],
+        );
+        metrics.single(
+            "moderation_cache_entries",
+            "gauge",
+            "Moderation results kept in memory, expired ones included until evicted",
+            self.entries().by_key.len() as u64,
+        );
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn evicts_oldest_and_expired() {
+        let cache = ModerationCache::in_memory(2, Duration::from_secs(60));
+        let (first, second, third) = (
+            ModerationCache::key("apilayer", "first"),
+            ModerationCache::key("apilayer", "second"),
+            ModerationCache::key("apilayer", "third"),
+        );
+        assert_ne!(first, ModerationCache::key("word-list", "first"));
+
+        cache.put(&first, "1");
+        cache.put(&second, "2");
+        cache.put(&first, "one");
+        assert_eq!(cache.get(&first).await.as_deref(), Some("one"));
+        cache.put(&third, "3");
+        assert_eq!(cache.get(&first).await.as_deref(), Some("one"));
+        assert_eq!(cache.get(&second).await, None);
+        assert_eq!(cache.get(&third).await.as_deref(), Some("3"));
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 3);
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 1);
+
+        let cache = ModerationCache::in_memory(2, Duration::from_millis(20));
+        cache.put(&first, "1");
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get(&first).await, None);
+        cache.put(&second, "2");
+        assert_eq!(cache.entries().by_key.len(), 1);
+
+        let disabled = ModerationCache::in_memory(0, Duration::from_secs(60));
+        disabled.put(&first, "1");
+        assert_eq!(disabled.get(&first).await, None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{collections::HashSet, fs};
+
+use async_trait::async_trait;
+use handle_errors::Error;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::config::Config;
+use crate::moderation::ContentModerator;
+
+const CENSOR_CHARACTER: char = '*';
+/// Characters standing in for letters, so `sh1t` and `$hit` are caught too
+const LEETSPEAK: &[(char, char)] = &[
+    ('0', 'o'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('3', 'e'),
+    ('4', 'a'),
+    ('@', 'a'),
+    ('5', 's'),
+    ('$', 's'),
+    ('7', 't'),
+];
+
+/// Censors the words of a local list, without calling any external service.
+/// Only whole words are censored, a listed `ass` leaves `class` alone.
+#[derive(Debug, Clone)]
+pub struct WordListModerator {
+    words: HashSet<String>,
+}
+
+impl WordListModerator {
+    /// Loads `--moderation-word-list`, one word per line. Empty lines
+    /// and lines starting with `#` are skipped.
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let path = config.moderation_word_list.as_ref().ok_or_else(|| {
+            Error::ConfigError("The word-list moderator needs --moderation-word-list".to_string())
+        })?;
+        let content = fs::read(path).map_err(Error::IoError)?;
+        let content = String::from_utf8_lossy(&content);
+        let moderator = WordListModerator::from_words(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.starts_with('#')),
+        );
+
+        tracing::event!(
+            tracing::Level::INFO,
+            words = moderator.words.len(),
+            "moderation word list loaded"
+        );
+
+        Ok(moderator)
+    }
+
+    pub fn from_words<'a>(words: impl IntoIterator<Item = &'a str>) -> Self {
+        WordListModerator {
+            words: words
+                .into_iter()
+                .map(normalize_word)
+                .filter(|word| !word.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Replaces every character of a listed word, the rest of the text is kept as it is
+    pub fn censor_text(&self, content: &str) -> String {
+        let mut censored = String::with_capacity(content.len());
+        let mut rest = content;
+
+        while let Some(start) = rest.find(is_word_character) {
+            censored.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c| !is_word_character(c)).unwrap_or(rest.len());
+            // A trailing `!` ends the sentence rather than standing in for an `i`
+            let word = rest[..end].trim_end_matches('!');
+
+            if self.words.contains(&normalize_word(word)) {
+                censored.extend(word.chars().map(|_| CENSOR_CHARACTER));
+            } else {
+                censored.push_str(word);
+            }
+            censored.push_str(&rest[word.len()..end]);
+            rest = &rest[end..];
+        }
+        censored.push_str(rest);
+
+        censored
+    }
+}
+
+#[async_trait]
+impl ContentModerator for WordListModerator {
+    async fn censor(&self, content: String, _request_id: &str) -> Result<String, Error> {
+        Ok(self.censor_text(&content))
+    }
+}
+
+fn is_word_character(c: char) -> bool {
+    c.is_alphanumeric() || is_combining_mark(c) || LEETSPEAK.iter().any(|(leet, _)| *leet == c)
+}
+
+/// Lowercase, without diacritics and with leetspeak replaced by letters,
+/// so `Shït` and `SH1T` are the same word
+fn normalize_word(word: &str) -> String {
+    word.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .map(|c| {
+            LEETSPEAK
+                .iter()
+                .find(|(leet, _)| *leet == c)
+                .map(|(_, letter)| *letter)
+                .unwrap_or(c)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod word_list_tests {
+    use super::*;
+
+    #[test]
+    fn censors_whole_words() {
+        let moderator = WordListModerator::from_words(["Shit", "ass", ""]);
+
+        assert_eq!(
+            moderator.censor_text("This is a shitty sentence"),
+            "This is a shitty sentence"
+        );
+        assert_eq!(
+            moderator.censor_text("Sh1t, what a $HIT! Shït happens."),
+            "****, what a ****! **** happens."
+        );
+        assert_eq!(
+            moderator.censor_text("Pass the class assignment, you ass!!"),
+            "Pass the class assignment, you ***!!"
+        );
+        assert_eq!(moderator.censor_text("!!! 4 @ 5"), "!!! 4 @ 5");
+        assert_eq!(moderator.censor_text(""), "");
+    }
+}
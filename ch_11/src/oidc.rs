@@ -0,0 +1,339 @@
+use std::{env, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use rand::Rng;
+use reqwest::Url;
+use serde::Deserialize;
+
+use handle_errors::Error;
+
+use crate::config::Config;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything the callback needs to finish a login the provider redirects back to
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+}
+
+/// Claims of the ID token this service relies on
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub exp: i64,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+/// `aud` is either a single client id or a list of them
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Authorization code flow with PKCE against the configured OpenID Connect provider.
+/// The client secret is read from `OIDC_CLIENT_SECRET`, public clients leave it unset.
+#[derive(Clone)]
+pub struct OidcClient {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    client_id: String,
+    client_secret: Option<Arc<String>>,
+    scopes: String,
+    redirect_uri: String,
+    http: reqwest::Client,
+}
+
+impl OidcClient {
+    /// Returns `None` if no provider is configured
+    pub fn new(config: &Config) -> Result<Option<Self>, Error> {
+        let issuer = match &config.oidc_issuer {
+            Some(issuer) => issuer.clone(),
+            None => return Ok(None),
+        };
+        let required = |value: &Option<String>, name: &str| {
+            value.clone().ok_or_else(|| {
+                Error::ConfigError(format!("{} is required for OpenID Connect", name))
+            })
+        };
+        let authorization_endpoint = required(
+            &config.oidc_authorization_endpoint,
+            "oidc_authorization_endpoint",
+        )?;
+        let token_endpoint = required(&config.oidc_token_endpoint, "oidc_token_endpoint")?;
+        let client_id = required(&config.oidc_client_id, "oidc_client_id")?;
+
+        for endpoint in [&authorization_endpoint, &token_endpoint] {
+            Url::parse(endpoint)
+                .map_err(|e| Error::ConfigError(format!("Invalid URL {}: {}", endpoint, e)))?;
+        }
+
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(Error::ReqwestAPIError)?;
+
+        tracing::event!(
+            tracing::Level::INFO,
+            issuer = issuer.as_str(),
+            "OpenID Connect login enabled"
+        );
+
+        Ok(Some(OidcClient {
+            issuer,
+            authorization_endpoint,
+            token_endpoint,
+            client_id,
+            client_secret: env::var("OIDC_CLIENT_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty())
+                .map(Arc::new),
+            scopes: config.oidc_scopes.clone(),
+            redirect_uri: format!(
+                "{}/auth/oidc/callback",
+                config.public_url.trim_end_matches('/')
+            ),
+            http,
+        }))
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    /// Builds the URL the user is sent to, with a fresh state, nonce and PKCE verifier
+    pub fn authorization_request(
+        &self,
+        login_hint: Option<&str>,
+    ) -> Result<AuthorizationRequest, Error> {
+        let state = random_token();
+        let code_verifier = random_token();
+        let nonce = random_token();
+        let code_challenge = code_challenge(&code_verifier);
+
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("scope", self.scopes.as_str()),
+            ("state", state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+        if let Some(login_hint) = login_hint {
+            params.push(("login_hint", login_hint));
+        }
+
+        let url = Url::parse_with_params(&self.authorization_endpoint, &params)
+            .map_err(|e| Error::ConfigError(e.to_string()))?;
+
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state,
+            code_verifier,
+            nonce,
+        })
+    }
+
+    /// Redeems the authorization code and returns the validated claims of the ID token
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, Error> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let res = self
+            .http
+            .post(&self.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(Error::ReqwestAPIError)?;
+
+        if !res.status().is_success() {
+            return Err(Error::OidcError(format!(
+                "token endpoint answered with {}",
+                res.status()
+            )));
+        }
+
+        let tokens = res
+            .json::<TokenResponse>()
+            .await
+            .map_err(|e| Error::OidcError(format!("invalid token response: {}", e)))?;
+        let claims = decode_id_token(&tokens.id_token)?;
+        self.validate_claims(&claims, nonce, Utc::now().timestamp())?;
+
+        Ok(claims)
+    }
+
+    fn validate_claims(&self, claims: &IdTokenClaims, nonce: &str, now: i64) -> Result<(), Error> {
+        if claims.iss != self.issuer {
+            return Err(Error::OidcError(format!(
+                "unexpected issuer {}",
+                claims.iss
+            )));
+        }
+        if !claims.aud.contains(&self.client_id) {
+            return Err(Error::OidcError(
+                "ID token is meant for another client".to_string(),
+            ));
+        }
+        if claims.exp <= now {
+            return Err(Error::OidcError("ID token expired".to_string()));
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(Error::OidcError("nonce does not match".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// The ID token comes straight from the token endpoint over TLS, so its
+/// signature does not have to be checked (OpenID Connect Core 3.1.3.7)
+fn decode_id_token(id_token: &str) -> Result<IdTokenClaims, Error> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok())
+        .ok_or_else(|| Error::OidcError("malformed ID token".to_string()))?;
+
+    serde_json::from_slice(&payload)
+        .map_err(|e| Error::OidcError(format!("invalid ID token claims: {}", e)))
+}
+
+fn random_token() -> String {
+    base64::encode_config(
+        rand::thread_rng().gen::<[u8; 32]>(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// PKCE `S256` challenge of the verifier (RFC 7636)
+fn code_challenge(code_verifier: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, code_verifier.as_bytes());
+    base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod oidc_tests {
+    use super::*;
+
+    fn client() -> OidcClient {
+        OidcClient {
+            issuer: "https://idp.example.com".to_string(),
+            authorization_endpoint: "https://idp.example.com/authorize".to_string(),
+            token_endpoint: "https://idp.example.com/token".to_string(),
+            client_id: "rust-web-dev".to_string(),
+            client_secret: None,
+            scopes: "openid email".to_string(),
+            redirect_uri: "http://localhost:8080/auth/oidc/callback".to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    #[test]
+    fn builds_pkce_authorization_request() {
+        // Example from RFC 7636, Appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+
+        let request = client()
+            .authorization_request(Some("bob@example.com"))
+            .unwrap();
+        let url = Url::parse(&request.url).unwrap();
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+
+        assert_eq!(param("state"), Some(request.state.clone()));
+        assert_eq!(param("nonce"), Some(request.nonce.clone()));
+        assert_eq!(
+            param("code_challenge"),
+            Some(code_challenge(&request.code_verifier))
+        );
+        assert_eq!(param("code_challenge_method").as_deref(), Some("S256"));
+        assert_eq!(param("login_hint").as_deref(), Some("bob@example.com"));
+    }
+
+    #[test]
+    fn validates_id_token_claims() {
+        let client = client();
+        let payload = serde_json::json!({
+            "iss": "https://idp.example.com",
+            "sub": "42",
+            "aud": ["other", "rust-web-dev"],
+            "exp": 1_000,
+            "nonce": "n",
+            "email": "bob@example.com",
+            "email_verified": true,
+        });
+        let id_token = format!(
+            "eyJhbGciOiJub25lIn0.{}.",
+            base64::encode_config(payload.to_string(), base64::URL_SAFE_NO_PAD)
+        );
+
+        let claims = decode_id_token(&id_token).unwrap();
+        assert_eq!(claims.email.as_deref(), Some("bob@example.com"));
+        assert!(client.validate_claims(&claims, "n", 999).is_ok());
+
+        assert!(client.validate_claims(&claims, "other nonce", 999).is_err());
+        assert!(client.validate_claims(&claims, "n", 1_000).is_err());
+        let foreign = IdTokenClaims {
+            aud: Audience::One("other".to_string()),
+            ..claims.clone()
+        };
+        assert!(client.validate_claims(&foreign, "n", 999).is_err());
+        let forged = IdTokenClaims {
+            iss: "https://evil.example.com".to_string(),
+            ..claims
+        };
+        assert!(client.validate_claims(&forged, "n", 999).is_err());
+
+        assert!(decode_id_token("not a token").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{env, sync::Arc};
+
+use argon2::{ThreadMode, Variant, Version};
+use rand::Rng;
+
+use handle_errors::Error;
+
+use crate::config::Config;
+
+/// Outcome of comparing a password with a stored hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Failed,
+    Valid,
+    /// The password matches, but the hash was produced with other
+    /// parameters or without the pepper and should be replaced
+    NeedsRehash,
+}
+
+/// Hashes and verifies passwords with the Argon2 parameters of the deployment.
+/// The optional pepper is read from `PASSWORD_PEPPER`, so it never ends up
+/// in the database next to the hashes.
+#[derive(Clone)]
+pub struct PasswordHasher {
+    variant: Variant,
+    mem_cost: u32,
+    time_cost: u32,
+    lanes: u32,
+    pepper: Option<Arc<Vec<u8>>>,
+    /// Verified instead of a real hash for unknown accounts
+    dummy_hash: Arc<String>,
+}
+
+impl PasswordHasher {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let variant =
+            Variant::from_str(&config.argon2_variant).map_err(Error::ArgonLibraryError)?;
+        let pepper = env::var("PASSWORD_PEPPER")
+            .ok()
+            .filter(|pepper| !pepper.is_empty())
+            .map(|pepper| Arc::new(pepper.into_bytes()));
+
+        let mut hasher = PasswordHasher {
+            variant,
+            mem_cost: config.argon2_memory_cost,
+            time_cost: config.argon2_iterations,
+            lanes: config.argon2_parallelism,
+            pepper,
+            dummy_hash: Arc::new(String::new()),
+        };
+        // Also rejects invalid parameters at startup instead of at the first registration
+        hasher.dummy_hash = Arc::new(hasher.hash(b"not the password of any account")?);
+
+        Ok(hasher)
+    }
+
+    pub fn hash(&self, password: &[u8]) -> Result<String, Error> {
+        let salt = rand::thread_rng().gen::<[u8; 32]>();
+        argon2::hash_encoded(password, &salt, &self.argon2_config())
+            .map_err(Error::ArgonLibraryError)
+    }
+
+    /// Hashes which cannot be decoded, like the `!` of accounts created through
+    /// an OpenID Connect login, match no password. The dummy hash is verified
+    /// instead, so the response time does not reveal such accounts.
+    pub fn verify(&self, hash: &str, password: &[u8]) -> Result<Verification, argon2::Error> {
+        match self.verify_hash(hash, password) {
+            Err(argon2::Error::DecodingFail) => {
+                let _ = self.verify_hash(&self.dummy_hash, password);
+                Ok(Verification::Failed)
+            }
+            verification => verification,
+        }
+    }
+
+    fn verify_hash(&self, hash: &str, password: &[u8]) -> Result<Verification, argon2::Error> {
+        let pepper = match &self.pepper {
+            Some(pepper) => pepper,
+            None => {
+                return Ok(match argon2::verify_encoded(hash, password)? {
+                    false => Verification::Failed,
+                    true if self.is_outdated(hash) => Verification::NeedsRehash,
+                    true => Verification::Valid,
+                })
+            }
+        };
+
+        if argon2::verify_encoded_ext(hash, password, pepper, &[])? {
+            return Ok(match self.is_outdated(hash) {
+                true => Verification::NeedsRehash,
+                false => Verification::Valid,
+            });
+        }
+
+        // Hashes created before the pepper was introduced
+        match argon2::verify_encoded(hash, password)? {
+            true => Ok(Verification::NeedsRehash),
+            false => Ok(Verification::Failed),
+        }
+    }
+
+    /// Spends the same time as verifying the password of an existing account
+    pub fn verify_dummy(&self, password: &[u8]) {
+        let _ = self.verify_hash(&self.dummy_hash, password);
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        let current = format!(
+            "${}$v={}$m={},t={},p={}$",
+            self.variant.as_lowercase_str(),
+            Version::Version13.as_u32(),
+            self.mem_cost,
+            self.time_cost,
+            self.lanes
+        );
+        !hash.starts_with(&current)
+    }
+
+    fn argon2_config(&self) -> argon2::Config<'_> {
+        argon2::Config {
+            variant: self.variant,
+            version: Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            thread_mode: ThreadMode::from_threads(self.lanes),
+            secret: self.pepper.as_ref().map(|p| p.as_slice()).unwrap_or(&[]),
+            ad: &[],
+            hash_length: 32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod password_tests {
+    use super::*;
+
+    fn hasher(mem_cost: u32, pepper: Option<&str>) -> PasswordHasher {
+        PasswordHasher {
+            variant: Variant::Argon2id,
+            mem_cost,
+            time_cost: 1,
+            lanes: 1,
+            pepper: pepper.map(|p| Arc::new(p.as_bytes().to_vec())),
+            dummy_hash: Arc::new(String::new()),
+        }
+    }
+
+    #[test]
+    fn verifies_with_current_parameters() {
+        let peppered = hasher(1024, Some("pepper"));
+        let hash = peppered.hash(b"password").unwrap();
+
+        assert_eq!(peppered.verify(&hash, b"password"), Ok(Verification::Valid));
+        assert_eq!(peppered.verify(&hash, b"wrong"), Ok(Verification::Failed));
+        // The pepper is part of the hash, it cannot be verified without it
+        assert_eq!(
+            hasher(1024, None).verify(&hash, b"password"),
+            Ok(Verification::Failed)
+        );
+        // Accounts without a password
+        assert_eq!(peppered.verify("!", b"!"), Ok(Verification::Failed));
+    }
+
+    #[test]
+    fn outdated_hashes_need_a_rehash() {
+        let old = hasher(1024, None);
+        let hash = old.hash(b"password").unwrap();
+
+        // Raised memory cost
+        assert_eq!(
+            hasher(2048, None).verify(&hash, b"password"),
+            Ok(Verification::NeedsRehash)
+        );
+        // Pepper introduced after the hash was created
+        assert_eq!(
+            hasher(1024, Some("pepper")).verify(&hash, b"password"),
+            Ok(Verification::NeedsRehash)
+        );
+        assert_eq!(
+            hasher(1024, Some("pepper")).verify(&hash, b"wrong"),
+            Ok(Verification::Failed)
+        );
+
+        // Hashes of the previous default config are outdated as well
+        let legacy =
+            argon2::hash_encoded(b"password", b"somesaltsomesalt", &argon2::Config::default())
+                .unwrap();
+        assert_eq!(
+            hasher(1024, None).verify(&legacy, b"password"),
+            Ok(Verification::NeedsRehash)
+        );
+    }
+}
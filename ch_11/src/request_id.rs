@@ -0,0 +1,91 @@
+use std::convert::Infallible;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+use warp::{
+    http::HeaderMap,
+    trace::{Info, Trace},
+    Filter,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Id correlating the log lines, the response and the outbound calls of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        RequestId(Uuid::new_v4().to_string())
+    }
+
+    /// Keeps the id sent by the client or a proxy in front of us, as long as it
+    /// is safe to log and echo. Otherwise a new one is generated.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LENGTH
+                    && id
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+            })
+            .map(|id| RequestId(id.to_string()))
+            .unwrap_or_else(RequestId::generate)
+    }
+}
+
+/// The id of the request. `server::serve` puts it into the header before the
+/// routes see the request, so every filter reads the same one.
+pub fn request_id() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| RequestId::from_headers(&headers).0)
+}
+
+/// `warp::trace::request()` with the request id recorded on the span
+pub fn trace_request() -> Trace<impl Fn(Info<'_>) -> Span + Clone> {
+    warp::trace(|info: Info<'_>| {
+        let span = tracing::info_span!(
+            "request",
+            request_id = %RequestId::from_headers(info.request_headers()).0,
+            method = %info.method(),
+            path = %info.path(),
+            version = ?info.version(),
+            referer = tracing::field::Empty,
+        );
+        if let Some(referer) = info.referer() {
+            span.record("referer", display(referer));
+        }
+        tracing::debug!(parent: &span, "received request");
+
+        span
+    })
+}
+
+#[cfg(test)]
+mod request_id_tests {
+    use super::*;
+    use warp::http::HeaderValue;
+
+    #[test]
+    fn keeps_safe_incoming_ids() {
+        let mut headers = HeaderMap::new();
+        let generated = RequestId::from_headers(&headers);
+        assert_eq!(generated.0.len(), 36);
+        assert_ne!(generated, RequestId::from_headers(&headers));
+
+        headers.insert(
+            REQUEST_ID_HEADER,
+            HeaderValue::from_static("lb-1234.abc_def"),
+        );
+        assert_eq!(RequestId::from_headers(&headers).0, "lb-1234.abc_def");
+
+        for unsafe_id in ["", "two words", "x\u{7f}", &"x".repeat(129)] {
+            if let Ok(value) = HeaderValue::from_str(unsafe_id) {
+                headers.insert(REQUEST_ID_HEADER, value);
+                assert_ne!(RequestId::from_headers(&headers).0, unsafe_id);
+            }
+        }
+    }
+}
@@ -1,3 +1,10 @@
+pub mod account;
 pub mod answer;
 pub mod authentication;
+pub mod health;
+pub mod moderation;
+pub mod oidc;
 pub mod question;
+pub mod session;
+pub mod token;
+pub mod two_factor;
@@ -0,0 +1,145 @@
+use chrono::prelude::*;
+use tracing::{event, Level};
+
+use warp::http::StatusCode;
+
+use crate::password::{PasswordHasher, Verification};
+use crate::store::Store;
+use crate::types::account::{
+    AccountDeletion, AccountExport, AccountId, ProfileUpdate, RoleChange, Session,
+    DELETED_ACCOUNT_ID, NO_PASSWORD,
+};
+
+/// How recent the sign-in of an account without a password must be to delete it
+const REAUTHENTICATION_SECONDS: i64 = 5 * 60;
+
+/// Public profile of an account, which never includes the email address
+pub async fn get_profile(id: i32, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_profile(&AccountId(id)).await {
+        Ok(profile) => Ok(warp::reply::json(&profile)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn update_profile(
+    session: Session,
+    store: Store,
+    profile: ProfileUpdate,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.update_profile(&session.account_id, profile).await {
+        Ok(profile) => Ok(warp::reply::json(&profile)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Grants a new role to an account. Only reachable by admins.
+pub async fn update_role(
+    id: i32,
+    session: Session,
+    store: Store,
+    change: RoleChange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = AccountId(id);
+
+    match store
+        .update_role(&account_id, change.role, Utc::now())
+        .await
+    {
+        Ok(_) => {
+            event!(
+                Level::INFO,
+                admin = session.account_id.0,
+                account_id = id,
+                role = change.role.as_str(),
+                "account role changed by admin"
+            );
+            Ok(warp::reply::json(&format!(
+                "Account {} is now {}",
+                id,
+                change.role.as_str()
+            )))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Hands out everything we store about the account as a JSON download
+pub async fn export_account(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let account = store.get_account_by_id(&account_id).await?;
+
+    let export = AccountExport {
+        email: account.email,
+        email_verified_at: store.get_email_verified_at(&account_id).await?,
+        role: account.role,
+        profile: store.get_profile(&account_id).await?,
+        questions: store.get_questions_by_account(&account_id).await?,
+        answers: store.get_answers_by_account(&account_id).await?,
+        identities: store.get_identities(&account_id).await?,
+        tokens: store.get_tokens(&account_id).await?,
+        two_factor_enabled: store.get_two_factor(&account_id).await?.enabled,
+        sessions: store
+            .get_sessions(&account_id, session.sid.as_deref())
+            .await?,
+        exported_at: Utc::now(),
+    };
+
+    Ok(warp::reply::with_header(
+        warp::reply::json(&export),
+        "Content-Disposition",
+        "attachment; filename=\"account-export.json\"",
+    ))
+}
+
+/// Deletes the account after confirming the password, or for accounts without
+/// one, if the session was started at the identity provider a moment ago.
+/// Questions and answers stay online, attributed to the "deleted user" placeholder.
+pub async fn delete_account(
+    session: Session,
+    store: Store,
+    hasher: PasswordHasher,
+    deletion: AccountDeletion,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    if account_id == DELETED_ACCOUNT_ID {
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+
+    let account = store.get_account_by_id(&account_id).await?;
+    if account.password == NO_PASSWORD {
+        let signed_in_recently = session.iat.is_some_and(|iat| {
+            Utc::now() - iat < chrono::Duration::seconds(REAUTHENTICATION_SECONDS)
+        });
+        if !signed_in_recently {
+            return Err(warp::reject::custom(
+                handle_errors::Error::ReauthenticationRequired,
+            ));
+        }
+    } else {
+        match hasher.verify(&account.password, deletion.password.as_bytes()) {
+            Ok(Verification::Failed) => {
+                return Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+            }
+            Ok(_) => (),
+            Err(e) => {
+                return Err(warp::reject::custom(
+                    handle_errors::Error::ArgonLibraryError(e),
+                ))
+            }
+        }
+    }
+
+    match store.delete_account(&account_id).await {
+        Ok(_) => {
+            event!(Level::INFO, account_id = account_id.0, "account deleted");
+            Ok(warp::reply::with_status(
+                "Account deleted".to_string(),
+                StatusCode::OK,
+            ))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -1,28 +1,123 @@
-use std::collections::HashMap;
+use tracing::{event, Level};
 use warp::http::StatusCode;
 
-use crate::profanity::check_profanity;
+use crate::moderation::Moderator;
+use crate::routes::moderation::queue_for_review;
 use crate::store::Store;
-use crate::types::{account::Session, answer::Answer};
+use crate::types::{
+    account::{Role, Session},
+    answer::{Answer, NewAnswer},
+    moderation::ContentKind,
+};
 
 pub async fn add_answer(
     session: Session,
     store: Store,
-    params: HashMap<String, String>,
+    moderator: Moderator,
+    request_id: String,
+    new_answer: NewAnswer,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    let content = match check_profanity(params.get("content").unwrap().to_string()).await {
+    let content = match moderator.censor(new_answer.content, &request_id).await {
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
 
-    let answer = Answer {
-        content,
-        question_id: params.get("questionId").unwrap().parse().unwrap(),
+    let answer = NewAnswer {
+        content: content.content,
+        question_id: new_answer.question_id,
     };
 
-    match store.add_answer(answer, account_id).await {
-        Ok(_) => Ok(warp::reply::with_status("Answer added", StatusCode::OK)),
+    match store.clone().add_answer(answer, account_id).await {
+        Ok(answer_id) => {
+            if content.needs_review {
+                queue_for_review(&store, ContentKind::Answer, answer_id.0, &request_id).await;
+            }
+            Ok(warp::reply::with_status("Answer added", StatusCode::OK))
+        }
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
+
+pub async fn get_answers(
+    question_id: i32,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_answers(question_id).await {
+        Ok(answers) => Ok(warp::reply::json(&answers)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn update_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+    moderator: Moderator,
+    request_id: String,
+    answer: Answer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let is_owner = store.is_answer_owner(id, &account_id).await?;
+    if is_owner || session.role >= Role::Moderator {
+        let content = match moderator.censor(answer.content, &request_id).await {
+            Ok(res) => res,
+            Err(e) => return Err(warp::reject::custom(e)),
+        };
+
+        let answer = Answer {
+            content: content.content,
+            ..answer
+        };
+
+        match store.clone().update_answer(answer, id).await {
+            Ok(res) => {
+                if content.needs_review {
+                    queue_for_review(&store, ContentKind::Answer, id, &request_id).await;
+                }
+                if !is_owner {
+                    event!(
+                        Level::INFO,
+                        moderator = account_id.0,
+                        answer_id = id,
+                        "answer updated by moderator"
+                    );
+                }
+                Ok(warp::reply::json(&res))
+            }
+            Err(e) => Err(warp::reject::custom(e)),
+        }
+    } else {
+        Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+    }
+}
+
+pub async fn delete_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let is_owner = store.is_answer_owner(id, &account_id).await?;
+    if is_owner || session.role >= Role::Moderator {
+        match store.delete_answer(id).await {
+            Ok(_) => {
+                if !is_owner {
+                    event!(
+                        Level::INFO,
+                        moderator = account_id.0,
+                        answer_id = id,
+                        "answer deleted by moderator"
+                    );
+                }
+                Ok(warp::reply::with_status(
+                    format!("Answer {} deleted", id),
+                    StatusCode::OK,
+                ))
+            }
+            Err(e) => Err(warp::reject::custom(e)),
+        }
+    } else {
+        Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+    }
+}
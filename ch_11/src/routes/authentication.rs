@@ -1,108 +1,638 @@
-use argon2::{self, Config};
 use chrono::prelude::*;
 use rand::Rng;
-use std::{env, future};
-use warp::Filter;
+use std::{collections::HashMap, future};
+use warp::{
+    http::{header::SET_COOKIE, HeaderValue, Method},
+    Filter, Reply,
+};
 
+use crate::keys::KeyRing;
+use crate::mail::Mailer;
+use crate::password::{PasswordHasher, Verification};
+use crate::routes::token::{hash_token, is_personal_access_token};
+use crate::routes::two_factor::issue_challenge_token;
 use crate::store::Store;
-use crate::types::account::{Account, AccountId, Session};
+use crate::throttle::LoginThrottle;
+use crate::types::{
+    account::{Account, AccountId, EmailChange, EmailVerification, PasswordChange, Role, Session},
+    session::ClientInfo,
+    token::Scope,
+};
+use crate::validation::{normalize_email, validate_email, PasswordPolicy};
 
-pub async fn register(store: Store, account: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    let hashed_password = hash_password(account.password.as_bytes());
+/// Purpose of email verification tokens, so they can never be mistaken for a session token
+const EMAIL_VERIFICATION_PURPOSE: &str = "email_verification";
+const SESSION_LIFETIME_SECONDS: i64 = 24 * 60 * 60;
+/// HttpOnly cookie with the session token, set by `/login` for browser clients
+const SESSION_COOKIE: &str = "session";
+/// Readable by scripts, which have to echo it in `X-CSRF-Token`
+/// for every unsafe request authenticated by the session cookie
+const CSRF_COOKIE: &str = "csrf_token";
+const CSRF_HEADER: &str = "x-csrf-token";
+/// Longer user agents are cut off before they are stored with the session
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+pub async fn register(
+    store: Store,
+    mailer: Mailer,
+    policy: PasswordPolicy,
+    hasher: PasswordHasher,
+    keys: KeyRing,
+    account: Account,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let email = normalize_email(&account.email);
+
+    let mut errors = Vec::new();
+    errors.extend(validate_email("email", &email));
+    errors.extend(policy.check("password", &account.password));
+    if !errors.is_empty() {
+        return Err(warp::reject::custom(handle_errors::Error::ValidationError(
+            errors,
+        )));
+    }
+
+    let hashed_password = hasher.hash(account.password.as_bytes())?;
 
     let account = Account {
         id: account.id,
-        email: account.email,
+        email,
         password: hashed_password,
+        role: Role::User,
     };
+    let email = account.email.clone();
 
     match store.add_account(account).await {
-        Ok(_) => Ok(warp::reply::json(&"Account added".to_string())),
+        Ok(account_id) => {
+            send_verification(&mailer, &keys, account_id, &email).await;
+            Ok(warp::reply::json(&"Account added".to_string()))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn verify_email(
+    params: HashMap<String, String>,
+    store: Store,
+    keys: KeyRing,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let token = match params.get("token") {
+        Some(token) => token,
+        None => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::MissingParameters,
+            ))
+        }
+    };
+    let verification = verify_verification_token(token, &keys)?;
+
+    match store
+        .verify_email(&verification.account_id, &verification.email)
+        .await
+    {
+        Ok(true) => Ok(warp::reply::json(&"Email verified".to_string())),
+        Ok(false) => Err(warp::reject::custom(
+            handle_errors::Error::CannotDecryptToken,
+        )),
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
-pub async fn login(store: Store, login: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    match store.get_account(login.email).await {
-        Ok(account) => match verify_password(&account.password, login.password.as_bytes()) {
-            Ok(verified) => {
-                if verified {
-                    Ok(warp::reply::json(&issue_token(
-                        account.id.expect("id not found"),
-                    )))
-                } else {
-                    Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+pub async fn resend_verification(
+    session: Session,
+    store: Store,
+    mailer: Mailer,
+    keys: KeyRing,
+    interval: i64,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    if store.is_email_verified(&account_id).await? {
+        return Ok(warp::reply::json(&"Email already verified".to_string()));
+    }
+
+    match store.claim_verification_resend(&account_id, interval).await {
+        Ok(Some(email)) => {
+            send_verification(&mailer, &keys, account_id, &email).await;
+            Ok(warp::reply::json(&"Verification email sent".to_string()))
+        }
+        Ok(None) => {
+            let wait = store
+                .verification_resend_wait(&account_id, interval)
+                .await?;
+            Err(warp::reject::custom(handle_errors::Error::TooManyRequests(
+                wait,
+            )))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// A failing mail server should not fail the request, the user can
+/// always ask for a new link later on.
+async fn send_verification(mailer: &Mailer, keys: &KeyRing, account_id: AccountId, email: &str) {
+    let token = issue_verification_token(keys, account_id, email);
+    if let Err(e) = mailer.send_verification(email, &token).await {
+        tracing::event!(tracing::Level::ERROR, "{}", e);
+    }
+}
+
+pub async fn login(
+    store: Store,
+    throttle: LoginThrottle,
+    hasher: PasswordHasher,
+    keys: KeyRing,
+    session_cookies: bool,
+    client: ClientInfo,
+    login: Account,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let email = normalize_email(&login.email);
+    let throttle_keys = LoginThrottle::keys(&email, client.addr);
+    throttle.check(&throttle_keys)?;
+
+    match store.clone().get_account(email.clone()).await {
+        Ok(account) => match hasher.verify(&account.password, login.password.as_bytes()) {
+            Ok(Verification::Failed) => {
+                throttle.record_failure(&throttle_keys);
+                Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+            }
+            Ok(verification) => {
+                let account_id = account.id.expect("id not found");
+                if verification == Verification::NeedsRehash {
+                    rehash_password(&store, &hasher, &account_id, &login.password).await;
                 }
+
+                // The failed attempts are only forgotten after the second step,
+                // otherwise the password would reset the lockout for guessing codes
+                if store.get_two_factor(&account_id).await?.enabled {
+                    return Ok(warp::reply::json(&serde_json::json!({
+                        "two_factor_required": true,
+                        "challenge_token": issue_challenge_token(&keys, account_id),
+                    }))
+                    .into_response());
+                }
+
+                throttle.record_success(&email);
+                let token = start_session(&store, &keys, account_id, account.role, &client).await?;
+                Ok(token_reply(token, session_cookies))
+            }
+            Err(e) => {
+                throttle.record_failure(&throttle_keys);
+                Err(warp::reject::custom(
+                    handle_errors::Error::ArgonLibraryError(e),
+                ))
             }
-            Err(e) => Err(warp::reject::custom(
-                handle_errors::Error::ArgonLibraryError(e),
-            )),
         },
-        Err(_) => Err(warp::reject::custom(handle_errors::Error::WrongPassword)),
+        Err(handle_errors::Error::DatabaseQueryError(sqlx::Error::RowNotFound)) => {
+            // Spend the same time as for a known email, so response times
+            // do not reveal which emails have an account
+            hasher.verify_dummy(login.password.as_bytes());
+            throttle.record_failure(&throttle_keys);
+            Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
-fn hash_password(password: &[u8]) -> String {
-    let salt = rand::thread_rng().gen::<[u8; 32]>();
-    let config = Config::default();
-    argon2::hash_encoded(password, &salt, &config).unwrap()
+/// Replaces a hash with outdated Argon2 parameters while the plain password
+/// is at hand. A failure only means another attempt at the next login.
+async fn rehash_password(
+    store: &Store,
+    hasher: &PasswordHasher,
+    account_id: &AccountId,
+    password: &str,
+) {
+    let result = match hasher.hash(password.as_bytes()) {
+        Ok(hash) => store.update_password_hash(account_id, hash).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(_) => tracing::event!(
+            tracing::Level::INFO,
+            account_id = account_id.0,
+            "password rehashed with current parameters"
+        ),
+        Err(e) => tracing::event!(tracing::Level::ERROR, "Cannot rehash password: {}", e),
+    }
 }
 
-fn verify_password(hash: &str, password: &[u8]) -> Result<bool, argon2::Error> {
-    argon2::verify_encoded(hash, password)
+/// Returns the token in the body and, for browser clients, also as
+/// session cookie together with a fresh CSRF token
+pub fn token_reply(token: String, session_cookies: bool) -> warp::reply::Response {
+    let cookies = session_cookies.then(|| {
+        let csrf_token = base64::encode_config(
+            rand::thread_rng().gen::<[u8; 32]>(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        [
+            format!(
+                "{}={}; Max-Age={}; Path=/; HttpOnly; Secure; SameSite=Strict",
+                SESSION_COOKIE, token, SESSION_LIFETIME_SECONDS
+            ),
+            format!(
+                "{}={}; Max-Age={}; Path=/; Secure; SameSite=Strict",
+                CSRF_COOKIE, csrf_token, SESSION_LIFETIME_SECONDS
+            ),
+        ]
+    });
+
+    let mut response = warp::reply::json(&token).into_response();
+    for cookie in cookies.into_iter().flatten() {
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+
+    response
 }
 
-pub fn verify_token(token: String) -> Result<Session, handle_errors::Error> {
-    let key = env::var("PASETO_KEY").unwrap();
-    let token = paseto::tokens::validate_local_token(
-        &token,
-        None,
-        key.as_bytes(),
-        &paseto::tokens::TimeBackend::Chrono,
-    )
-    .map_err(|_| handle_errors::Error::CannotDecryptToken)?;
+/// Changes the password and signs out every other session of the account.
+/// The caller receives a fresh token, so only their current client stays logged in.
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password(
+    session: Session,
+    store: Store,
+    policy: PasswordPolicy,
+    hasher: PasswordHasher,
+    keys: KeyRing,
+    session_cookies: bool,
+    client: ClientInfo,
+    change: PasswordChange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let errors = policy.check("new_password", &change.new_password);
+    if !errors.is_empty() {
+        return Err(warp::reject::custom(handle_errors::Error::ValidationError(
+            errors,
+        )));
+    }
+
+    let account_id = session.account_id;
+    let account = store.get_account_by_id(&account_id).await?;
+
+    match hasher.verify(&account.password, change.current_password.as_bytes()) {
+        Ok(Verification::Failed) => {
+            return Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+        }
+        Ok(_) => (),
+        Err(e) => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::ArgonLibraryError(e),
+            ))
+        }
+    }
+
+    let hashed_password = hasher.hash(change.new_password.as_bytes())?;
+    let revoked_at = Utc::now();
+
+    match store
+        .update_password(&account_id, hashed_password, revoked_at)
+        .await
+    {
+        Ok(_) => {
+            tracing::event!(
+                tracing::Level::INFO,
+                account_id = account_id.0,
+                "password changed, other sessions revoked"
+            );
+            let token = start_session(&store, &keys, account_id, session.role, &client).await?;
+            Ok(token_reply(token, session_cookies))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn change_email(
+    session: Session,
+    store: Store,
+    mailer: Mailer,
+    hasher: PasswordHasher,
+    keys: KeyRing,
+    change: EmailChange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let email = normalize_email(&change.email);
+    if let Some(error) = validate_email("email", &email) {
+        return Err(warp::reject::custom(handle_errors::Error::ValidationError(
+            vec![error],
+        )));
+    }
+
+    let account_id = session.account_id;
+    let account = store.get_account_by_id(&account_id).await?;
+
+    match hasher.verify(&account.password, change.password.as_bytes()) {
+        Ok(Verification::Failed) => {
+            return Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+        }
+        Ok(_) => (),
+        Err(e) => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::ArgonLibraryError(e),
+            ))
+        }
+    }
+
+    match store.update_email(&account_id, &email).await {
+        Ok(_) => {
+            send_verification(&mailer, &keys, account_id, &email).await;
+            Ok(warp::reply::json(&"Email updated".to_string()))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub fn verify_token(token: String, keys: &KeyRing) -> Result<Session, handle_errors::Error> {
+    let token = keys.validate(&token)?;
     serde_json::from_value::<Session>(token).map_err(|_| handle_errors::Error::CannotDecryptToken)
 }
 
-fn issue_token(account_id: AccountId) -> String {
-    let key = env::var("PASETO_KEY").unwrap();
+fn verify_verification_token(
+    token: &str,
+    keys: &KeyRing,
+) -> Result<EmailVerification, handle_errors::Error> {
+    let token = keys.validate_for(EMAIL_VERIFICATION_PURPOSE, token)?;
+    serde_json::from_value::<EmailVerification>(token)
+        .map_err(|_| handle_errors::Error::CannotDecryptToken)
+}
+
+/// Records a new server-side session for the client and issues its token
+pub async fn start_session(
+    store: &Store,
+    keys: &KeyRing,
+    account_id: AccountId,
+    role: Role,
+    client: &ClientInfo,
+) -> Result<String, handle_errors::Error> {
+    let session_id = base64::encode_config(
+        rand::thread_rng().gen::<[u8; 16]>(),
+        base64::URL_SAFE_NO_PAD,
+    );
+    let expires_at = Utc::now() + chrono::Duration::seconds(SESSION_LIFETIME_SECONDS);
 
+    store
+        .add_session(&session_id, &account_id, expires_at, client)
+        .await?;
+
+    Ok(issue_token(keys, account_id, role, &session_id))
+}
+
+pub fn issue_token(keys: &KeyRing, account_id: AccountId, role: Role, session_id: &str) -> String {
     let current_date_time = Utc::now();
-    let dt = current_date_time + chrono::Duration::days(1);
-
-    paseto::tokens::PasetoBuilder::new()
-        .set_encryption_key(&Vec::from(key.as_bytes()))
-        .set_expiration(&dt)
-        .set_claim("account_id", serde_json::json!(account_id))
-        .build()
-        .expect("Failed to construct paseto token w/ builder!")
-}
-
-pub fn auth() -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
-    warp::header::<String>("Authorization").and_then(|token: String| {
-        let token = match verify_token(token) {
-            Ok(t) => t,
-            Err(_) => {
-                return future::ready(Err(warp::reject::custom(
-                    handle_errors::Error::Unauthorized,
-                )))
+    let dt = current_date_time + chrono::Duration::seconds(SESSION_LIFETIME_SECONDS);
+
+    keys.issue(&serde_json::json!({
+        "exp": dt.to_rfc3339(),
+        "iat": current_date_time.to_rfc3339(),
+        "account_id": account_id,
+        "role": role,
+        "sid": session_id,
+    }))
+}
+
+fn issue_verification_token(keys: &KeyRing, account_id: AccountId, email: &str) -> String {
+    let dt = Utc::now() + chrono::Duration::days(1);
+
+    keys.issue_for(
+        EMAIL_VERIFICATION_PURPOSE,
+        &serde_json::json!({
+            "exp": dt.to_rfc3339(),
+            "account_id": account_id,
+            "email": email,
+        }),
+    )
+}
+
+/// Public keys for other services to verify `v4.public` tokens, the
+/// key id in the footer of a token tells which one to use. Only sessions
+/// verify without an implicit assertion, verifiers should still reject
+/// tokens carrying a `purpose` claim.
+pub async fn public_keys(keys: KeyRing) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&serde_json::json!({
+        "keys": keys.public_keys(),
+    })))
+}
+
+/// User agent and address of the client, recorded with new sessions
+pub fn client_info() -> impl Filter<Extract = (ClientInfo,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("user-agent")
+        .and(crate::server::remote_addr())
+        .map(|user_agent: Option<String>, addr| ClientInfo {
+            user_agent: user_agent.map(|ua| ua.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+            addr,
+        })
+}
+
+/// The raw token of the request, a PASETO session or a personal access token.
+/// The session cookie is only read if `session_cookies` is enabled.
+fn token(
+    session_cookies: bool,
+) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    warp::method()
+        .and(warp::header::optional::<String>("Authorization"))
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and(warp::cookie::optional::<String>(CSRF_COOKIE))
+        .and(warp::header::optional::<String>(CSRF_HEADER))
+        .and_then(
+            move |method: Method,
+                  authorization: Option<String>,
+                  session_cookie: Option<String>,
+                  csrf_cookie: Option<String>,
+                  csrf_header: Option<String>| {
+                future::ready(
+                    request_token(
+                        &method,
+                        authorization,
+                        session_cookie.filter(|_| session_cookies),
+                        csrf_cookie,
+                        csrf_header,
+                    )
+                    .map_err(warp::reject::custom),
+                )
+            },
+        )
+}
+
+/// The `Authorization` header wins over the cookie. Cookies are sent by the
+/// browser on their own, so unsafe methods additionally need the CSRF token.
+fn request_token(
+    method: &Method,
+    authorization: Option<String>,
+    session_cookie: Option<String>,
+    csrf_cookie: Option<String>,
+    csrf_header: Option<String>,
+) -> Result<String, handle_errors::Error> {
+    if let Some(authorization) = authorization {
+        return Ok(bearer_token(&authorization).to_string());
+    }
+
+    let token = session_cookie.ok_or(handle_errors::Error::CannotDecryptToken)?;
+    if !method.is_safe() {
+        match (csrf_cookie, csrf_header) {
+            (Some(cookie), Some(header))
+                if ring::constant_time::verify_slices_are_equal(
+                    cookie.as_bytes(),
+                    header.as_bytes(),
+                )
+                .is_ok() => {}
+            _ => return Err(handle_errors::Error::Unauthorized),
+        }
+    }
+
+    Ok(token)
+}
+
+/// Accepts `Bearer <token>` as well as the bare token, which clients
+/// written before the scheme was supported still send
+fn bearer_token(authorization: &str) -> &str {
+    match authorization.trim().split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        _ => authorization.trim(),
+    }
+}
+
+/// Authenticates the request with a session or a personal access token and
+/// rejects sessions which have been signed out or revoked in the meantime
+/// (e.g. by a password change). Personal access tokens are limited to their scopes, so routes have
+/// to chain `require_scope()` or `require_session()`.
+pub fn auth(
+    store: Store,
+    keys: KeyRing,
+    session_cookies: bool,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    token(session_cookies).and_then(move |token: String| {
+        let store = store.clone();
+        let keys = keys.clone();
+        async move {
+            if is_personal_access_token(&token) {
+                return match store.use_token(&hash_token(&token)).await {
+                    Ok(Some(session)) => Ok(session),
+                    Ok(None) => Err(warp::reject::custom(
+                        handle_errors::Error::CannotDecryptToken,
+                    )),
+                    Err(e) => Err(warp::reject::custom(e)),
+                };
             }
-        };
 
-        future::ready(Ok(token))
+            let session = verify_token(token, &keys)
+                .map_err(|_| warp::reject::custom(handle_errors::Error::CannotDecryptToken))?;
+            if !store
+                .is_session_valid(&session.account_id, session.iat)
+                .await?
+            {
+                return Err(warp::reject::custom(
+                    handle_errors::Error::CannotDecryptToken,
+                ));
+            }
+
+            // Tokens from before sessions were tracked have no id, they
+            // stay valid until they expire
+            match &session.sid {
+                Some(sid) if !store.touch_session(sid, &session.account_id).await? => Err(
+                    warp::reject::custom(handle_errors::Error::CannotDecryptToken),
+                ),
+                _ => Ok(session),
+            }
+        }
+    })
+}
+
+/// Rejects personal access tokens without `scope`, sessions from `/login` always pass:
+/// `auth(store, keys).and_then(require_scope(Scope::AnswersWrite))`
+pub fn require_scope(
+    scope: Scope,
+) -> impl Fn(Session) -> future::Ready<Result<Session, warp::Rejection>> + Clone {
+    move |session: Session| match &session.scopes {
+        Some(scopes) if !scopes.contains(&scope) => future::ready(Err(warp::reject::custom(
+            handle_errors::Error::Unauthorized,
+        ))),
+        _ => future::ready(Ok(session)),
+    }
+}
+
+/// Rejects personal access tokens, for routes which manage the account itself
+pub fn require_session(session: Session) -> future::Ready<Result<Session, warp::Rejection>> {
+    match session.scopes {
+        Some(_) => future::ready(Err(warp::reject::custom(
+            handle_errors::Error::Unauthorized,
+        ))),
+        None => future::ready(Ok(session)),
+    }
+}
+
+/// Rejects sessions whose role is below `role`, to be chained after `auth()`:
+/// `auth(store, keys).and_then(require_role(Role::Moderator))`
+pub fn require_role(
+    role: Role,
+) -> impl Fn(Session) -> future::Ready<Result<Session, warp::Rejection>> + Clone {
+    move |session: Session| {
+        if session.role >= role {
+            future::ready(Ok(session))
+        } else {
+            future::ready(Err(warp::reject::custom(
+                handle_errors::Error::Unauthorized,
+            )))
+        }
+    }
+}
+
+/// Like `auth()`, but additionally rejects accounts which did not verify
+/// their email address yet when `required` is set
+pub fn verified(
+    store: Store,
+    keys: KeyRing,
+    session_cookies: bool,
+    required: bool,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    auth(store.clone(), keys, session_cookies).and_then(move |session: Session| {
+        let store = store.clone();
+        async move {
+            if !required {
+                return Ok(session);
+            }
+
+            match store.is_email_verified(&session.account_id).await {
+                Ok(true) => Ok(session),
+                Ok(false) => Err(warp::reject::custom(handle_errors::Error::EmailNotVerified)),
+                Err(e) => Err(warp::reject::custom(e)),
+            }
+        }
     })
 }
 
 #[cfg(test)]
 mod authentication_tests {
-    use super::{auth, env, issue_token, AccountId};
+    use super::{
+        bearer_token, future, issue_token, issue_verification_token, require_role, require_scope,
+        require_session, token, verify_token, verify_verification_token, AccountId, Filter,
+        KeyRing, Role, Scope, Session,
+    };
+    use crate::keys::TokenFormat;
+
+    /// Like `auth()`, without checking the session against the database
+    fn session_token(
+        keys: KeyRing,
+    ) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+        token(true).and_then(move |token: String| {
+            future::ready(
+                verify_token(token, &keys)
+                    .map_err(|_| warp::reject::custom(handle_errors::Error::Unauthorized)),
+            )
+        })
+    }
+
+    fn keys() -> KeyRing {
+        KeyRing::from_keys(
+            TokenFormat::V2Local,
+            "test",
+            &[("test", "RANDOM WORDS WINTER MACINTOSH PC")],
+        )
+        .unwrap()
+    }
 
     #[tokio::test]
     async fn post_questions_auth() {
-        env::set_var("PASETO_KEY", "RANDOM WORDS WINTER MACINTOSH PC");
-        let token = issue_token(AccountId(3));
+        let keys = keys();
+        let token = issue_token(&keys, AccountId(3), Role::User, "test");
 
-        let filter = auth();
+        let filter = session_token(keys.clone());
 
         let res = warp::test::request()
             .header("Authorization", token)
@@ -110,4 +640,126 @@ mod authentication_tests {
 
         assert_eq!(res.await.unwrap().account_id, AccountId(3));
     }
+
+    #[test]
+    fn verification_token_is_not_a_session() {
+        let keys = keys();
+        let token = issue_verification_token(&keys, AccountId(3), "test@email.com");
+
+        assert!(verify_token(token.clone(), &keys).is_err());
+
+        let verification = verify_verification_token(&token, &keys).unwrap();
+        assert_eq!(verification.account_id, AccountId(3));
+        assert_eq!(verification.email, "test@email.com");
+
+        assert!(verify_verification_token(
+            &issue_token(&keys, AccountId(3), Role::User, "test"),
+            &keys
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn require_role_rejects_lower_roles() {
+        let keys = keys();
+        let filter = session_token(keys.clone()).and_then(require_role(Role::Moderator));
+
+        let res = warp::test::request()
+            .header(
+                "Authorization",
+                issue_token(&keys, AccountId(3), Role::User, "test"),
+            )
+            .filter(&filter);
+        assert!(res.await.is_err());
+
+        let res = warp::test::request()
+            .header(
+                "Authorization",
+                issue_token(&keys, AccountId(3), Role::Admin, "test"),
+            )
+            .filter(&filter);
+        assert_eq!(res.await.unwrap().role, Role::Admin);
+    }
+
+    #[test]
+    fn parses_bearer_and_bare_tokens() {
+        assert_eq!(bearer_token("Bearer v2.local.abc"), "v2.local.abc");
+        assert_eq!(bearer_token("bearer  v2.local.abc "), "v2.local.abc");
+        assert_eq!(bearer_token("v2.local.abc"), "v2.local.abc");
+    }
+
+    #[tokio::test]
+    async fn cookie_sessions_need_csrf_token_for_unsafe_methods() {
+        let keys = keys();
+        let token = issue_token(&keys, AccountId(3), Role::User, "test");
+        let filter = session_token(keys.clone());
+        let cookie = format!("session={}; csrf_token=abc", token);
+
+        let res = warp::test::request()
+            .method("GET")
+            .header("Cookie", &cookie)
+            .filter(&filter);
+        assert_eq!(res.await.unwrap().account_id, AccountId(3));
+
+        let res = warp::test::request()
+            .method("POST")
+            .header("Cookie", &cookie)
+            .filter(&filter);
+        assert!(res.await.is_err());
+
+        let res = warp::test::request()
+            .method("POST")
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", "abd")
+            .filter(&filter);
+        assert!(res.await.is_err());
+
+        let res = warp::test::request()
+            .method("POST")
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", "abc")
+            .filter(&filter);
+        assert_eq!(res.await.unwrap().account_id, AccountId(3));
+
+        let res = warp::test::request()
+            .method("POST")
+            .header("Authorization", format!("Bearer {}", token))
+            .filter(&filter);
+        assert_eq!(res.await.unwrap().account_id, AccountId(3));
+
+        let without_cookies = super::token(false);
+        let res = warp::test::request()
+            .method("GET")
+            .header("Cookie", &cookie)
+            .filter(&without_cookies);
+        assert!(res.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn personal_access_tokens_are_limited_to_their_scopes() {
+        let token_session = Session {
+            exp: chrono::Utc::now(),
+            account_id: AccountId(3),
+            iat: None,
+            role: Role::User,
+            sid: None,
+            scopes: Some(vec![Scope::AnswersWrite]),
+        };
+        assert!(require_scope(Scope::AnswersWrite)(token_session.clone())
+            .await
+            .is_ok());
+        assert!(require_scope(Scope::QuestionsWrite)(token_session.clone())
+            .await
+            .is_err());
+        assert!(require_session(token_session.clone()).await.is_err());
+
+        let login_session = Session {
+            scopes: None,
+            ..token_session
+        };
+        assert!(require_scope(Scope::QuestionsWrite)(login_session.clone())
+            .await
+            .is_ok());
+        assert!(require_session(login_session).await.is_ok());
+    }
 }
@@ -0,0 +1,53 @@
+use warp::http::{header::CONTENT_TYPE, StatusCode};
+
+use crate::circuit_breaker::CircuitState;
+use crate::metrics::Metrics;
+use crate::moderation::Moderator;
+use crate::store::Store;
+use crate::types::health::{Health, HealthStatus};
+
+/// State of the database and of the moderation calls. Answers with 503 if
+/// the database is unreachable, an open circuit only degrades the service.
+pub async fn health(
+    store: Store,
+    moderator: Moderator,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let database = match store.ping().await {
+        Ok(_) => HealthStatus::Ok,
+        Err(_) => HealthStatus::Unavailable,
+    };
+    let moderation = moderator.health();
+
+    let status = if database == HealthStatus::Unavailable {
+        HealthStatus::Unavailable
+    } else if moderation.circuit != CircuitState::Closed {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Ok
+    };
+    let code = match status {
+        HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&Health {
+            status,
+            database,
+            moderation,
+        }),
+        code,
+    ))
+}
+
+/// Metrics in the Prometheus text format
+pub async fn metrics(moderator: Moderator) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut metrics = Metrics::new();
+    moderator.write_metrics(&mut metrics);
+
+    Ok(warp::reply::with_header(
+        metrics.into_text(),
+        CONTENT_TYPE,
+        "text/plain; version=0.0.4",
+    ))
+}
@@ -0,0 +1,60 @@
+use tracing::{event, Level};
+use warp::http::StatusCode;
+
+use crate::store::Store;
+use crate::types::{account::Session, moderation::ContentKind};
+
+/// Queues content the failure policy let through uncensored. The content is
+/// stored already, so a failure is logged rather than failing the request.
+pub async fn queue_for_review(store: &Store, kind: ContentKind, content_id: i32, request_id: &str) {
+    if store
+        .queue_review(kind, content_id, request_id)
+        .await
+        .is_err()
+    {
+        event!(
+            Level::ERROR,
+            content_type = kind.as_str(),
+            content_id,
+            "content stored without review"
+        );
+    }
+}
+
+/// Content stored uncensored while moderation was unavailable, oldest first
+pub async fn get_reviews(
+    _session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_reviews().await {
+        Ok(reviews) => Ok(warp::reply::json(&reviews)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Marks the content as reviewed, after the moderator edited or deleted it if needed
+pub async fn resolve_review(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.delete_review(id).await {
+        Ok(true) => {
+            event!(
+                target: "audit",
+                Level::INFO,
+                moderator = session.account_id.0,
+                review_id = id,
+                "moderation review resolved"
+            );
+            Ok(warp::reply::with_status(
+                format!("Review {} resolved", id),
+                StatusCode::OK,
+            ))
+        }
+        Ok(false) => Err(warp::reject::custom(
+            handle_errors::Error::DatabaseQueryError(sqlx::Error::RowNotFound),
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -0,0 +1,400 @@
+use chrono::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use warp::http::{header::SET_COOKIE, HeaderValue, Uri};
+use warp::Reply;
+
+use crate::keys::KeyRing;
+use crate::oidc::{IdTokenClaims, OidcClient};
+use crate::routes::authentication::{start_session, token_reply};
+use crate::routes::two_factor::issue_challenge_token;
+use crate::store::Store;
+use crate::types::{
+    account::{AccountId, Role},
+    session::ClientInfo,
+};
+use crate::validation::{normalize_email, validate_email};
+
+/// Purpose of the token in the state cookie
+const OIDC_LOGIN_PURPOSE: &str = "oidc_login";
+/// Remembers the pending login between `/auth/oidc/start` and the callback.
+/// `SameSite=Lax`, as the provider redirects back with a cross-site navigation.
+const STATE_COOKIE: &str = "oidc_state";
+const STATE_LIFETIME_SECONDS: i64 = 10 * 60;
+
+/// Claims of the state cookie token
+#[derive(Deserialize)]
+struct LoginState {
+    state: String,
+    code_verifier: String,
+    nonce: String,
+}
+
+/// Redirects to the identity provider. State, nonce and PKCE verifier travel
+/// in a token issued with our own keys, so no server-side storage is needed.
+pub async fn start(
+    params: HashMap<String, String>,
+    oidc: OidcClient,
+    keys: KeyRing,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let request = oidc.authorization_request(params.get("login_hint").map(String::as_str))?;
+    let exp = Utc::now() + chrono::Duration::seconds(STATE_LIFETIME_SECONDS);
+
+    let state_token = keys.issue_for(
+        OIDC_LOGIN_PURPOSE,
+        &serde_json::json!({
+            "exp": exp.to_rfc3339(),
+            "state": request.state,
+            "code_verifier": request.code_verifier,
+            "nonce": request.nonce,
+        }),
+    );
+    let location = request
+        .url
+        .parse::<Uri>()
+        .map_err(|e| handle_errors::Error::ConfigError(e.to_string()))?;
+
+    Ok(warp::reply::with_header(
+        warp::redirect::found(location),
+        SET_COOKIE,
+        format!(
+            "{}={}; Max-Age={}; Path=/auth/oidc; HttpOnly; Secure; SameSite=Lax",
+            STATE_COOKIE, state_token, STATE_LIFETIME_SECONDS
+        ),
+    ))
+}
+
+/// The provider redirects back here. Signs in the linked account, or
+/// links/creates one, and hands out the same session as `/login`. Like
+/// there, accounts with two-factor authentication get a challenge instead.
+pub async fn callback(
+    params: HashMap<String, String>,
+    state_cookie: Option<String>,
+    store: Store,
+    oidc: OidcClient,
+    keys: KeyRing,
+    session_cookies: bool,
+    client: ClientInfo,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(error) = params.get("error") {
+        return Err(warp::reject::custom(handle_errors::Error::OidcError(
+            format!("provider returned {}", error),
+        )));
+    }
+    let (code, state) = match (params.get("code"), params.get("state")) {
+        (Some(code), Some(state)) => (code, state),
+        _ => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::MissingParameters,
+            ))
+        }
+    };
+
+    let login_state = state_cookie
+        .ok_or_else(|| handle_errors::Error::OidcError("missing state cookie".to_string()))
+        .and_then(|cookie| verify_login_state(&cookie, &keys))?;
+    if ring::constant_time::verify_slices_are_equal(login_state.state.as_bytes(), state.as_bytes())
+        .is_err()
+    {
+        return Err(warp::reject::custom(handle_errors::Error::OidcError(
+            "state does not match".to_string(),
+        )));
+    }
+
+    let claims = oidc
+        .exchange_code(code, &login_state.code_verifier, &login_state.nonce)
+        .await?;
+    let (account_id, role) = link_account(&store, oidc.issuer(), &claims).await?;
+
+    let mut response = if store.get_two_factor(&account_id).await?.enabled {
+        warp::reply::json(&serde_json::json!({
+            "two_factor_required": true,
+            "challenge_token": issue_challenge_token(&keys, account_id),
+        }))
+        .into_response()
+    } else {
+        let token = start_session(&store, &keys, account_id, role, &client).await?;
+        token_reply(token, session_cookies)
+    };
+    let clear_state = format!(
+        "{}=; Max-Age=0; Path=/auth/oidc; HttpOnly; Secure; SameSite=Lax",
+        STATE_COOKIE
+    );
+    if let Ok(cookie) = HeaderValue::from_str(&clear_state) {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+
+    Ok(response)
+}
+
+/// An identity seen before signs in to its account. Otherwise an account with
+/// the same email is only linked if the provider verified the email, so nobody
+/// can take over an account by registering its email at some provider.
+async fn link_account(
+    store: &Store,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> Result<(AccountId, Role), handle_errors::Error> {
+    if let Some(account) = store.get_account_by_identity(issuer, &claims.sub).await? {
+        return Ok((account.id.expect("id not found"), account.role));
+    }
+
+    let email = claims
+        .email
+        .as_deref()
+        .map(normalize_email)
+        .filter(|email| validate_email("email", email).is_none())
+        .ok_or_else(|| {
+            handle_errors::Error::OidcError("ID token has no valid email claim".to_string())
+        })?;
+
+    let (account_id, role) = match store.find_account_by_email(&email).await? {
+        Some(account) if claims.email_verified => (account.id.expect("id not found"), account.role),
+        Some(_) => return Err(handle_errors::Error::EmailAlreadyInUse),
+        None => (
+            store
+                .add_external_account(&email, claims.email_verified)
+                .await?,
+            Role::User,
+        ),
+    };
+
+    store.add_identity(issuer, &claims.sub, &account_id).await?;
+    tracing::event!(
+        target: "audit",
+        tracing::Level::INFO,
+        account_id = account_id.0,
+        issuer = issuer,
+        "external identity linked"
+    );
+
+    Ok((account_id, role))
+}
+
+fn verify_login_state(token: &str, keys: &KeyRing) -> Result<LoginState, handle_errors::Error> {
+    let token = keys.validate_for(OIDC_LOGIN_PURPOSE, token)?;
+    serde_json::from_value::<LoginState>(token)
+        .map_err(|_| handle_errors::Error::CannotDecryptToken)
+}
+
+#[cfg(test)]
+mod oidc_route_tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::keys::TokenFormat;
+    use crate::routes::authentication::{issue_token, verify_token};
+    use crate::types::account::Account;
+    use clap::Parser;
+    use mock_server::{MockServer, OneshotHandler};
+    use reqwest::Url;
+    use warp::http::header::LOCATION;
+
+    fn keys() -> KeyRing {
+        KeyRing::from_keys(
+            TokenFormat::V2Local,
+            "test",
+            &[("test", "RANDOM WORDS WINTER MACINTOSH PC")],
+        )
+        .unwrap()
+    }
+
+    /// Database from `DATABASE_URL` and a mock identity provider on a free port
+    async fn setup() -> (Store, OidcClient, OneshotHandler) {
+        let store = Store::new(&std::env::var("DATABASE_URL").expect("DATABASE_URL not set"))
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&store.connection).await.unwrap();
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let mock = MockServer::new(([127, 0, 0, 1], port).into());
+        let issuer = mock.oidc_issuer();
+        let config = Config::parse_from([
+            "rust-web-dev",
+            "--oidc-issuer",
+            &issuer,
+            "--oidc-authorization-endpoint",
+            &format!("{}/authorize", issuer),
+            "--oidc-token-endpoint",
+            &format!("{}/token", issuer),
+            "--oidc-client-id",
+            mock_server::OIDC_CLIENT_ID,
+        ]);
+        let oidc = OidcClient::new(&config).unwrap().unwrap();
+
+        (store, oidc, mock.oneshot())
+    }
+
+    fn unique_email(name: &str) -> String {
+        format!("{}.{}@example.com", name, rand::random::<u32>())
+    }
+
+    /// Runs `/auth/oidc/start`, the provider and the callback. `provider_params`
+    /// are added to the authorization request, `tamper` changes the redirect back.
+    async fn sign_in(
+        store: &Store,
+        oidc: &OidcClient,
+        email: &str,
+        provider_params: &str,
+        tamper: impl FnOnce(&mut HashMap<String, String>),
+    ) -> Result<warp::reply::Response, warp::Rejection> {
+        let keys = keys();
+        let params = HashMap::from([("login_hint".to_string(), email.to_string())]);
+        let start = start(params, oidc.clone(), keys.clone())
+            .await?
+            .into_response();
+        let header = |name| start.headers()[name].to_str().unwrap().to_string();
+        let state_cookie = header(SET_COOKIE)
+            .split(';')
+            .next()
+            .and_then(|cookie| cookie.strip_prefix("oidc_state="))
+            .unwrap()
+            .to_string();
+
+        let provider = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(format!("{}{}", header(LOCATION), provider_params))
+            .send()
+            .await
+            .unwrap();
+        let redirect = Url::parse(provider.headers()[LOCATION].to_str().unwrap()).unwrap();
+        let mut params: HashMap<String, String> = redirect.query_pairs().into_owned().collect();
+        tamper(&mut params);
+
+        callback(
+            params,
+            Some(state_cookie),
+            store.clone(),
+            oidc.clone(),
+            keys,
+            false,
+            ClientInfo {
+                user_agent: None,
+                addr: None,
+            },
+        )
+        .await
+        .map(Reply::into_response)
+    }
+
+    async fn session_account(response: warp::reply::Response) -> AccountId {
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let token: String = serde_json::from_slice(&body).unwrap();
+        let claims = keys().validate(&token).unwrap();
+        serde_json::from_value(claims["account_id"].clone()).unwrap()
+    }
+
+    #[test]
+    fn state_cookie_is_not_a_session() {
+        let keys = keys();
+        let session = issue_token(&keys, AccountId(3), Role::User, "test");
+        assert!(verify_login_state(&session, &keys).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database, see DATABASE_URL"]
+    async fn signs_in_through_the_provider() {
+        let (store, oidc, _mock) = setup().await;
+        let subject = |email: &str| format!("mock|{}", email);
+
+        // Unknown email: a new account is created and signed in
+        let email = unique_email("oidc.new");
+        let response = sign_in(&store, &oidc, &email, "", |_| {}).await.unwrap();
+        let account_id = session_account(response).await;
+        let sessions = store.get_sessions(&account_id, None).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        let account = store.find_account_by_email(&email).await.unwrap().unwrap();
+        assert_eq!(account.id, Some(account_id.clone()));
+        let linked = store
+            .get_account_by_identity(oidc.issuer(), &subject(&email))
+            .await
+            .unwrap();
+        assert_eq!(
+            linked.and_then(|account| account.id),
+            Some(account_id.clone())
+        );
+
+        // The linked identity signs in to the same account again
+        let response = sign_in(&store, &oidc, &email, "", |_| {}).await.unwrap();
+        assert_eq!(session_account(response).await, account_id);
+
+        // Existing account with an email the provider verified: linked
+        let email = unique_email("oidc.existing");
+        let existing = store
+            .clone()
+            .add_account(Account {
+                id: None,
+                email: email.clone(),
+                password: "hash".to_string(),
+                role: Role::User,
+            })
+            .await
+            .unwrap();
+        let response = sign_in(&store, &oidc, &email, "", |_| {}).await.unwrap();
+        assert_eq!(session_account(response).await, existing);
+
+        // Existing account with an unverified email: not linked
+        let email = unique_email("oidc.unverified");
+        store
+            .clone()
+            .add_account(Account {
+                id: None,
+                email: email.clone(),
+                password: "hash".to_string(),
+                role: Role::User,
+            })
+            .await
+            .unwrap();
+        let rejection = sign_in(&store, &oidc, &email, "&email_verified=false", |_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            rejection.find(),
+            Some(handle_errors::Error::EmailAlreadyInUse)
+        ));
+        let linked = store
+            .get_account_by_identity(oidc.issuer(), &subject(&email))
+            .await
+            .unwrap();
+        assert!(linked.is_none());
+
+        // The state sent back by the provider must match the state cookie
+        let email = unique_email("oidc.state");
+        let rejection = sign_in(&store, &oidc, &email, "", |params| {
+            params.insert("state".to_string(), "forged".to_string());
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            rejection.find(),
+            Some(handle_errors::Error::OidcError(_))
+        ));
+        assert!(store.find_account_by_email(&email).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database, see DATABASE_URL"]
+    async fn asks_for_the_second_factor() {
+        let (store, oidc, _mock) = setup().await;
+        let email = unique_email("oidc.two_factor");
+        let response = sign_in(&store, &oidc, &email, "", |_| {}).await.unwrap();
+        let account_id = session_account(response).await;
+        assert!(store.set_totp_secret(&account_id, &[7; 20]).await.unwrap());
+        assert!(store.enable_two_factor(&account_id, 0, &[]).await.unwrap());
+
+        let response = sign_in(&store, &oidc, &email, "", |_| {}).await.unwrap();
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let challenge: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(challenge["two_factor_required"], true);
+        let challenge_token = challenge["challenge_token"].as_str().unwrap();
+        assert!(verify_token(challenge_token.to_string(), &keys()).is_err());
+    }
+}
@@ -3,9 +3,11 @@ use std::collections::HashMap;
 use tracing::{event, instrument, Level};
 use warp::http::StatusCode;
 
-use crate::profanity::check_profanity;
+use crate::moderation::Moderator;
+use crate::routes::moderation::queue_for_review;
 use crate::store::Store;
-use crate::types::account::Session;
+use crate::types::account::{Role, Session};
+use crate::types::moderation::ContentKind;
 use crate::types::pagination::{extract_pagination, Pagination};
 use crate::types::question::{NewQuestion, Question};
 
@@ -35,30 +37,43 @@ pub async fn update_question(
     id: i32,
     session: Session,
     store: Store,
+    moderator: Moderator,
+    request_id: String,
     question: Question,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    if store.is_question_owner(id, &account_id).await? {
-        let title = check_profanity(question.title);
-        let content = check_profanity(question.content);
+    let is_owner = store.is_question_owner(id, &account_id).await?;
+    if is_owner || session.role >= Role::Moderator {
+        let title = moderator.censor(question.title, &request_id);
+        let content = moderator.censor(question.content, &request_id);
 
         let (title, content) = tokio::join!(title, content);
+        let (title, content) = (title?, content?);
+        let needs_review = title.needs_review || content.needs_review;
 
-        if title.is_ok() && content.is_ok() {
-            let question = Question {
-                id: question.id,
-                title: title.unwrap(),
-                content: content.unwrap(),
-                tags: question.tags,
-            };
-            match store.update_question(question, id, account_id).await {
-                Ok(res) => Ok(warp::reply::json(&res)),
-                Err(e) => Err(warp::reject::custom(e)),
+        let question = Question {
+            id: question.id,
+            title: title.content,
+            content: content.content,
+            tags: question.tags,
+            author: None,
+        };
+        match store.clone().update_question(question, id).await {
+            Ok(res) => {
+                if needs_review {
+                    queue_for_review(&store, ContentKind::Question, id, &request_id).await;
+                }
+                if !is_owner {
+                    event!(
+                        Level::INFO,
+                        moderator = account_id.0,
+                        question_id = id,
+                        "question updated by moderator"
+                    );
+                }
+                Ok(warp::reply::json(&res))
             }
-        } else {
-            Err(warp::reject::custom(
-                title.expect_err("Expected API call to have failed here"),
-            ))
+            Err(e) => Err(warp::reject::custom(e)),
         }
     } else {
         Err(warp::reject::custom(handle_errors::Error::Unauthorized))
@@ -71,12 +86,23 @@ pub async fn delete_question(
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    if store.is_question_owner(id, &account_id).await? {
-        match store.delete_question(id, account_id).await {
-            Ok(_) => Ok(warp::reply::with_status(
-                format!("Question {} deleted", id),
-                StatusCode::OK,
-            )),
+    let is_owner = store.is_question_owner(id, &account_id).await?;
+    if is_owner || session.role >= Role::Moderator {
+        match store.delete_question(id).await {
+            Ok(_) => {
+                if !is_owner {
+                    event!(
+                        Level::INFO,
+                        moderator = account_id.0,
+                        question_id = id,
+                        "question deleted by moderator"
+                    );
+                }
+                Ok(warp::reply::with_status(
+                    format!("Question {} deleted", id),
+                    StatusCode::OK,
+                ))
+            }
             Err(e) => Err(warp::reject::custom(e)),
         }
     } else {
@@ -87,27 +113,35 @@ pub async fn delete_question(
 pub async fn add_question(
     session: Session,
     store: Store,
+    moderator: Moderator,
+    request_id: String,
     new_question: NewQuestion,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    let title = match check_profanity(new_question.title).await {
+    let title = match moderator.censor(new_question.title, &request_id).await {
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
 
-    let content = match check_profanity(new_question.content).await {
+    let content = match moderator.censor(new_question.content, &request_id).await {
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
 
+    let needs_review = title.needs_review || content.needs_review;
     let question = NewQuestion {
-        title,
-        content,
+        title: title.content,
+        content: content.content,
         tags: new_question.tags,
     };
 
-    match store.add_question(question, account_id).await {
-        Ok(question) => Ok(warp::reply::json(&question)),
+    match store.clone().add_question(question, account_id).await {
+        Ok(question) => {
+            if needs_review {
+                queue_for_review(&store, ContentKind::Question, question.id.0, &request_id).await;
+            }
+            Ok(warp::reply::json(&question))
+        }
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
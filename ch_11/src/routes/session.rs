@@ -0,0 +1,47 @@
+use tracing::{event, Level};
+
+use warp::http::StatusCode;
+
+use crate::store::Store;
+use crate::types::account::Session;
+
+/// Every client the account is signed in with, the requesting one marked as `current`
+pub async fn get_sessions(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store
+        .get_sessions(&session.account_id, session.sid.as_deref())
+        .await
+    {
+        Ok(sessions) => Ok(warp::reply::json(&sessions)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Signs out a session, its token is rejected from the next request on
+pub async fn delete_session(
+    id: String,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.delete_session(&session.account_id, &id).await {
+        Ok(true) => {
+            event!(
+                target: "audit",
+                Level::INFO,
+                account_id = session.account_id.0,
+                "session signed out"
+            );
+            Ok(warp::reply::with_status(
+                "Session signed out".to_string(),
+                StatusCode::OK,
+            ))
+        }
+        // Sessions of other accounts are not found either, their ids stay secret
+        Ok(false) => Err(warp::reject::custom(
+            handle_errors::Error::DatabaseQueryError(sqlx::Error::RowNotFound),
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
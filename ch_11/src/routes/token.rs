@@ -0,0 +1,125 @@
+use rand::Rng;
+use tracing::{event, Level};
+
+use warp::http::StatusCode;
+
+use crate::store::Store;
+use crate::types::{
+    account::Session,
+    token::{CreatedToken, NewToken, TokenId},
+};
+
+/// Tells personal access tokens apart from PASETO sessions, and lets
+/// secret scanners recognize leaked tokens
+const TOKEN_PREFIX: &str = "qa_pat_";
+
+pub fn is_personal_access_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// Tokens are 256 random bits and cannot be guessed like passwords,
+/// so a plain SHA-256 is enough and keeps the lookup cheap
+pub fn hash_token(token: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, token.as_bytes());
+    base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+fn generate_token() -> String {
+    format!(
+        "{}{}",
+        TOKEN_PREFIX,
+        base64::encode_config(
+            rand::thread_rng().gen::<[u8; 32]>(),
+            base64::URL_SAFE_NO_PAD
+        )
+    )
+}
+
+/// Creates a personal access token. The response is the only time the token
+/// is shown, afterwards only its hash is known.
+pub async fn create_token(
+    session: Session,
+    store: Store,
+    new_token: NewToken,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut scopes = Vec::new();
+    for scope in new_token.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let token = generate_token();
+    let info = store
+        .add_token(
+            &session.account_id,
+            &new_token.name,
+            &hash_token(&token),
+            &scopes,
+            new_token.expires_at,
+        )
+        .await?;
+
+    event!(
+        target: "audit",
+        Level::INFO,
+        account_id = session.account_id.0,
+        token_id = info.id.0,
+        "personal access token created"
+    );
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&CreatedToken { token, info }),
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn get_tokens(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_tokens(&session.account_id).await {
+        Ok(tokens) => Ok(warp::reply::json(&tokens)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn revoke_token(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.delete_token(&session.account_id, &TokenId(id)).await {
+        Ok(true) => {
+            event!(
+                target: "audit",
+                Level::INFO,
+                account_id = session.account_id.0,
+                token_id = id,
+                "personal access token revoked"
+            );
+            Ok(warp::reply::with_status(
+                format!("Token {} revoked", id),
+                StatusCode::OK,
+            ))
+        }
+        Ok(false) => Err(warp::reject::custom(handle_errors::Error::Unauthorized)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+#[cfg(test)]
+mod token_tests {
+    use super::*;
+
+    #[test]
+    fn generates_recognizable_tokens() {
+        let token = generate_token();
+
+        assert!(is_personal_access_token(&token));
+        assert!(!is_personal_access_token("v2.local.abc"));
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), hash_token(&generate_token()));
+    }
+}
@@ -0,0 +1,245 @@
+use chrono::prelude::*;
+use tracing::{event, Level};
+
+use warp::http::StatusCode;
+
+use crate::keys::KeyRing;
+use crate::password::{PasswordHasher, Verification};
+use crate::routes::authentication::{start_session, token_reply};
+use crate::store::Store;
+use crate::throttle::LoginThrottle;
+use crate::totp;
+use crate::types::{
+    account::{AccountId, Session},
+    session::ClientInfo,
+    two_factor::{
+        RecoveryCodes, TwoFactorChallenge, TwoFactorCode, TwoFactorDisable, TwoFactorLogin,
+        TwoFactorSetup,
+    },
+};
+
+/// Purpose of challenge tokens, so they can never be mistaken for a session token
+const TWO_FACTOR_PURPOSE: &str = "two_factor_challenge";
+const CHALLENGE_LIFETIME_SECONDS: i64 = 5 * 60;
+
+/// Starts the setup with a fresh secret. Two-factor authentication is only
+/// enforced after `confirm`, so a failed scan does not lock anybody out.
+pub async fn setup(
+    session: Session,
+    store: Store,
+    issuer: String,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = store.get_account_by_id(&session.account_id).await?;
+    let secret = totp::generate_secret();
+
+    if !store.set_totp_secret(&session.account_id, &secret).await? {
+        return Err(warp::reject::custom(
+            handle_errors::Error::TwoFactorAlreadyEnabled,
+        ));
+    }
+
+    Ok(warp::reply::json(&TwoFactorSetup {
+        secret: totp::base32_encode(&secret),
+        otpauth_uri: totp::otpauth_uri(&issuer, &account.email, &secret),
+    }))
+}
+
+/// Enables two-factor authentication once the user proves their authenticator
+/// works, and hands out the recovery codes. They are never shown again.
+pub async fn confirm(
+    session: Session,
+    store: Store,
+    confirmation: TwoFactorCode,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let two_factor = store.get_two_factor(&account_id).await?;
+    if two_factor.enabled {
+        return Err(warp::reject::custom(
+            handle_errors::Error::TwoFactorAlreadyEnabled,
+        ));
+    }
+    let secret = two_factor
+        .secret
+        .ok_or(handle_errors::Error::TwoFactorNotEnabled)?;
+    let step = totp::verify(&secret, &confirmation.code, Utc::now().timestamp())
+        .ok_or(handle_errors::Error::InvalidTwoFactorCode)?;
+
+    let recovery_codes = totp::generate_recovery_codes();
+    let hashes = recovery_codes
+        .iter()
+        .map(|code| totp::hash_recovery_code(code))
+        .collect::<Vec<_>>();
+    if !store.enable_two_factor(&account_id, step, &hashes).await? {
+        return Err(warp::reject::custom(
+            handle_errors::Error::TwoFactorAlreadyEnabled,
+        ));
+    }
+
+    event!(
+        target: "audit",
+        Level::INFO,
+        account_id = account_id.0,
+        "two-factor authentication enabled"
+    );
+
+    Ok(warp::reply::json(&RecoveryCodes { recovery_codes }))
+}
+
+/// Needs the password and a code, a stolen session alone cannot turn it off
+pub async fn disable(
+    session: Session,
+    store: Store,
+    hasher: PasswordHasher,
+    disable: TwoFactorDisable,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = session.account_id;
+    let account = store.get_account_by_id(&account_id).await?;
+
+    match hasher.verify(&account.password, disable.password.as_bytes()) {
+        Ok(Verification::Failed) => {
+            return Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+        }
+        Ok(_) => (),
+        Err(e) => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::ArgonLibraryError(e),
+            ))
+        }
+    }
+
+    check_code(&store, &account_id, &disable.code).await?;
+
+    match store.disable_two_factor(&account_id).await {
+        Ok(_) => {
+            event!(
+                target: "audit",
+                Level::INFO,
+                account_id = account_id.0,
+                "two-factor authentication disabled"
+            );
+            Ok(warp::reply::with_status(
+                "Two-factor authentication disabled".to_string(),
+                StatusCode::OK,
+            ))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Second step of the login, exchanges the challenge from `/login` and
+/// a code for the session. Wrong codes count as failed logins.
+pub async fn login(
+    store: Store,
+    throttle: LoginThrottle,
+    keys: KeyRing,
+    session_cookies: bool,
+    client: ClientInfo,
+    login: TwoFactorLogin,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let challenge = verify_challenge_token(&login.challenge_token, &keys)?;
+    let account = store.get_account_by_id(&challenge.account_id).await?;
+    let throttle_keys = LoginThrottle::keys(&account.email, client.addr);
+    throttle.check(&throttle_keys)?;
+
+    match check_code(&store, &challenge.account_id, &login.code).await {
+        Ok(()) => {
+            throttle.record_success(&account.email);
+            let token =
+                start_session(&store, &keys, challenge.account_id, account.role, &client).await?;
+            Ok(token_reply(token, session_cookies))
+        }
+        Err(handle_errors::Error::InvalidTwoFactorCode) => {
+            throttle.record_failure(&throttle_keys);
+            Err(warp::reject::custom(
+                handle_errors::Error::InvalidTwoFactorCode,
+            ))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Accepts a TOTP code which was not used before, or an unused recovery code
+async fn check_code(
+    store: &Store,
+    account_id: &AccountId,
+    code: &str,
+) -> Result<(), handle_errors::Error> {
+    let two_factor = store.get_two_factor(account_id).await?;
+    let secret = match two_factor.secret {
+        Some(secret) if two_factor.enabled => secret,
+        _ => return Err(handle_errors::Error::TwoFactorNotEnabled),
+    };
+
+    if let Some(step) = totp::verify(&secret, code, Utc::now().timestamp()) {
+        return match store.use_totp_step(account_id, step).await? {
+            true => Ok(()),
+            false => Err(handle_errors::Error::InvalidTwoFactorCode),
+        };
+    }
+
+    if store
+        .use_recovery_code(account_id, &totp::hash_recovery_code(code))
+        .await?
+    {
+        event!(
+            target: "audit",
+            Level::WARN,
+            account_id = account_id.0,
+            "recovery code used"
+        );
+        return Ok(());
+    }
+
+    Err(handle_errors::Error::InvalidTwoFactorCode)
+}
+
+pub fn issue_challenge_token(keys: &KeyRing, account_id: AccountId) -> String {
+    let dt = Utc::now() + chrono::Duration::seconds(CHALLENGE_LIFETIME_SECONDS);
+
+    keys.issue_for(
+        TWO_FACTOR_PURPOSE,
+        &serde_json::json!({
+            "exp": dt.to_rfc3339(),
+            "account_id": account_id,
+        }),
+    )
+}
+
+fn verify_challenge_token(
+    token: &str,
+    keys: &KeyRing,
+) -> Result<TwoFactorChallenge, handle_errors::Error> {
+    let token = keys.validate_for(TWO_FACTOR_PURPOSE, token)?;
+    serde_json::from_value::<TwoFactorChallenge>(token)
+        .map_err(|_| handle_errors::Error::CannotDecryptToken)
+}
+
+#[cfg(test)]
+mod two_factor_tests {
+    use super::*;
+    use crate::keys::TokenFormat;
+    use crate::routes::authentication::{issue_token, verify_token};
+    use crate::types::account::Role;
+
+    #[test]
+    fn challenge_token_is_not_a_session() {
+        let keys = KeyRing::from_keys(
+            TokenFormat::V2Local,
+            "test",
+            &[("test", "RANDOM WORDS WINTER MACINTOSH PC")],
+        )
+        .unwrap();
+
+        let challenge = issue_challenge_token(&keys, AccountId(3));
+        assert!(verify_token(challenge.clone(), &keys).is_err());
+        assert_eq!(
+            verify_challenge_token(&challenge, &keys)
+                .unwrap()
+                .account_id,
+            AccountId(3)
+        );
+
+        let session = issue_token(&keys, AccountId(3), Role::User, "test");
+        assert!(verify_challenge_token(&session, &keys).is_err());
+    }
+}
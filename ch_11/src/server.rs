@@ -0,0 +1,186 @@
+use std::{convert::Infallible, future::Future, net::SocketAddr, panic::AssertUnwindSafe};
+
+use futures::FutureExt;
+use tracing::{event, Instrument, Level};
+use warp::{
+    http::{
+        header::{ACCEPT, ACCEPT_LANGUAGE},
+        HeaderValue, StatusCode,
+    },
+    hyper::{
+        server::conn::{AddrIncoming, AddrStream},
+        service::{make_service_fn, service_fn, Service},
+        Server,
+    },
+    reply::Response,
+    Filter,
+};
+
+use handle_errors::{Locale, Problem, ProblemFormat};
+
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+
+/// Address of the client. Only `warp::serve` can hand it to `warp::addr::remote`.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// The client address set by `serve`, or the one known to warp
+pub fn remote_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::ext::optional::<RemoteAddr>()
+        .and(warp::addr::remote())
+        .map(|ext: Option<RemoteAddr>, addr: Option<SocketAddr>| ext.map(|ext| ext.0).or(addr))
+}
+
+/// Serves the routes like `warp::serve`, but a panicking handler results in a
+/// logged 500 with the request id instead of a connection closed without response.
+/// Every response carries the id of its request in `X-Request-Id`.
+pub async fn serve<F>(
+    routes: F,
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), handle_errors::Error>
+where
+    F: Filter<Extract = (Response,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    let (_, server) = bind(routes, addr, shutdown)?;
+    server.await
+}
+
+/// Like `serve`, but binds right away and also returns the bound address,
+/// which tells the port picked for port 0
+pub fn bind<F>(
+    routes: F,
+    addr: SocketAddr,
+    shutdown: impl Future<Output = ()>,
+) -> Result<
+    (
+        SocketAddr,
+        impl Future<Output = Result<(), handle_errors::Error>>,
+    ),
+    handle_errors::Error,
+>
+where
+    F: Filter<Extract = (Response,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let service = service.clone();
+        let remote_addr = conn.remote_addr();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req| {
+                let mut service = service.clone();
+                let header = |name| {
+                    req.headers()
+                        .get(name)
+                        .and_then(|value: &HeaderValue| value.to_str().ok())
+                };
+                let format = ProblemFormat::negotiate(header(ACCEPT));
+                let locale = Locale::negotiate(header(ACCEPT_LANGUAGE));
+                // Always valid, unsafe incoming ids are replaced by `from_headers`
+                let request_id = RequestId::from_headers(req.headers());
+                let header = HeaderValue::from_str(&request_id.0).ok();
+                if let Some(header) = &header {
+                    req.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+                }
+                req.extensions_mut().insert(RemoteAddr(remote_addr));
+
+                let span = tracing::info_span!("client", remote.addr = %remote_addr);
+                async move {
+                    let mut response = match AssertUnwindSafe(service.call(req))
+                        .catch_unwind()
+                        .await
+                    {
+                        Ok(Ok(response)) => response,
+                        Ok(Err(infallible)) => match infallible {},
+                        Err(panic) => {
+                            let message = panic
+                                .downcast_ref::<&str>()
+                                .copied()
+                                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                                .unwrap_or("unknown cause");
+                            event!(
+                                Level::ERROR,
+                                request_id = request_id.0.as_str(),
+                                "request handler panicked: {}",
+                                message
+                            );
+
+                            let mut problem =
+                                Problem::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error");
+                            problem.request_id = Some(request_id.0);
+                            problem.localize(locale);
+                            problem.render(format)
+                        }
+                    };
+                    if let Some(header) = header {
+                        response.headers_mut().insert(REQUEST_ID_HEADER, header);
+                    }
+
+                    Ok::<_, Infallible>(response)
+                }
+                .instrument(span)
+            }))
+        }
+    });
+
+    let mut incoming = AddrIncoming::bind(&addr)
+        .map_err(|e| handle_errors::Error::ConfigError(format!("Cannot bind {}: {}", addr, e)))?;
+    incoming.set_nodelay(true);
+    let local_addr = incoming.local_addr();
+
+    let server = Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown);
+
+    Ok((local_addr, async move {
+        server
+            .await
+            .map_err(|e| handle_errors::Error::IoError(std::io::Error::other(e)))
+    }))
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+    use crate::request_id::request_id;
+
+    #[tokio::test]
+    async fn answers_panics_with_internal_server_error() {
+        let routes = warp::path("panic")
+            .map(|| -> &'static str { panic!("handler bug") })
+            .or(warp::path("ok").and(request_id()).map(|id: String| id));
+        let routes = handle_errors::recover_problems(routes, request_id());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let (addr, server) = bind(routes, ([127, 0, 0, 1], 0).into(), async {
+            rx.await.ok();
+        })
+        .unwrap();
+        tokio::spawn(server);
+
+        let res = reqwest::get(format!("http://{}/panic", addr))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 500);
+        let header = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let problem = res.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(problem["code"], "internal_error");
+        assert_eq!(problem["request_id"], header.as_str());
+
+        // The server keeps serving, and the handlers see the id of the client
+        let res = reqwest::Client::new()
+            .get(format!("http://{}/ok", addr))
+            .header(REQUEST_ID_HEADER, "from-client")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "from-client");
+        assert_eq!(res.text().await.unwrap(), "from-client");
+
+        let _ = tx.send(());
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::prelude::*;
 use sqlx::{
     postgres::{PgPool, PgPoolOptions, PgRow},
     Row,
@@ -6,9 +7,16 @@ use sqlx::{
 use handle_errors::Error;
 
 use crate::types::{
-    account::{Account, AccountId},
-    answer::Answer,
+    account::{
+        Account, AccountId, Author, LinkedIdentity, Profile, ProfileUpdate, Role, Session,
+        DELETED_ACCOUNT_ID, NO_PASSWORD,
+    },
+    answer::{Answer, AnswerId, NewAnswer},
+    moderation::{ContentKind, Review},
     question::{NewQuestion, Question, QuestionId},
+    session::{ClientInfo, SessionInfo},
+    token::{Scope, TokenId, TokenInfo},
+    two_factor::TwoFactor,
 };
 
 #[derive(Debug, Clone)]
@@ -34,17 +42,25 @@ impl Store {
         limit: Option<i32>,
         offset: i32,
     ) -> Result<Vec<Question>, Error> {
-        match sqlx::query("SELECT * from questions LIMIT $1 OFFSET $2")
-            .bind(limit)
-            .bind(offset)
-            .map(|row: PgRow| Question {
-                id: QuestionId(row.get("id")),
-                title: row.get("title"),
-                content: row.get("content"),
-                tags: row.get("tags"),
-            })
-            .fetch_all(&self.connection)
-            .await
+        match sqlx::query(
+            "SELECT questions.*, accounts.display_name from questions
+            LEFT JOIN accounts ON accounts.id = questions.account_id
+            LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(|row: PgRow| Question {
+            id: QuestionId(row.get("id")),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            author: Some(Author {
+                id: AccountId(row.get("account_id")),
+                display_name: row.get("display_name"),
+            }),
+        })
+        .fetch_all(&self.connection)
+        .await
         {
             Ok(questions) => Ok(questions),
             Err(e) => {
@@ -78,7 +94,8 @@ impl Store {
         new_question: NewQuestion,
         account_id: AccountId,
     ) -> Result<Question, Error> {
-        match sqlx::query("INSERT INTO questions (title, content, tags, account_id) VALUES ($1, $2, $3, $4) RETURNING id, title, content, tags")
+        match sqlx::query("WITH question AS (INSERT INTO questions (title, content, tags, account_id) VALUES ($1, $2, $3, $4) RETURNING id, title, content, tags, account_id)
+            SELECT question.*, accounts.display_name FROM question LEFT JOIN accounts ON accounts.id = question.account_id")
             .bind(new_question.title)
             .bind(new_question.content)
             .bind(new_question.tags)
@@ -88,6 +105,10 @@ impl Store {
                 title: row.get("title"),
                 content: row.get("content"),
                 tags: row.get("tags"),
+                author: Some(Author {
+                    id: AccountId(row.get("account_id")),
+                    display_name: row.get("display_name"),
+                }),
             })
             .fetch_one(&self.connection)
             .await {
@@ -99,27 +120,28 @@ impl Store {
             }
     }
 
-    pub async fn update_question(
-        self,
-        question: Question,
-        id: i32,
-        account_id: AccountId,
-    ) -> Result<Question, Error> {
+    /// Ownership is checked by the caller, as moderators may edit any question
+    pub async fn update_question(self, question: Question, id: i32) -> Result<Question, Error> {
         match sqlx::query(
-            "UPDATE questions SET title = $1, content = $2, tags = $3
-        WHERE id = $4 AND account_id = $5
-        RETURNING id, title, content, tags",
+            "WITH question AS (UPDATE questions SET title = $1, content = $2, tags = $3
+        WHERE id = $4
+        RETURNING id, title, content, tags, account_id)
+        SELECT question.*, accounts.display_name FROM question
+        LEFT JOIN accounts ON accounts.id = question.account_id",
         )
         .bind(question.title)
         .bind(question.content)
         .bind(question.tags)
         .bind(id)
-        .bind(account_id.0)
         .map(|row: PgRow| Question {
             id: QuestionId(row.get("id")),
             title: row.get("title"),
             content: row.get("content"),
             tags: row.get("tags"),
+            author: Some(Author {
+                id: AccountId(row.get("account_id")),
+                display_name: row.get("display_name"),
+            }),
         })
         .fetch_one(&self.connection)
         .await
@@ -132,10 +154,10 @@ impl Store {
         }
     }
 
-    pub async fn delete_question(self, id: i32, account_id: AccountId) -> Result<bool, Error> {
-        match sqlx::query("DELETE FROM questions WHERE id = $1 AND account_id = $2")
+    /// Ownership is checked by the caller, as moderators may delete any question
+    pub async fn delete_question(self, id: i32) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM questions WHERE id = $1")
             .bind(id)
-            .bind(account_id.0)
             .execute(&self.connection)
             .await
         {
@@ -147,68 +169,369 @@ impl Store {
         }
     }
 
-    pub async fn add_answer(self, answer: Answer, account_id: AccountId) -> Result<bool, Error> {
+    pub async fn add_answer(
+        self,
+        answer: NewAnswer,
+        account_id: AccountId,
+    ) -> Result<AnswerId, Error> {
         match sqlx::query(
-            "INSERT INTO answers (content, corresponding_question, account_id) VALUES ($1, $2, $3)",
+            "INSERT INTO answers (content, corresponding_question, account_id) VALUES ($1, $2, $3)
+            RETURNING id",
         )
         .bind(answer.content)
         .bind(answer.question_id)
         .bind(account_id.0)
-        .execute(&self.connection)
+        .map(|row: PgRow| AnswerId(row.get("id")))
+        .fetch_one(&self.connection)
         .await
         {
-            Ok(_) => Ok(true),
+            Ok(answer_id) => Ok(answer_id),
+            Err(error) => {
+                log_database_error(&error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn get_answers(&self, question_id: i32) -> Result<Vec<Answer>, Error> {
+        match sqlx::query(
+            "SELECT answers.*, accounts.display_name from answers
+            LEFT JOIN accounts ON accounts.id = answers.account_id
+            WHERE corresponding_question = $1
+            ORDER BY answers.created_on",
+        )
+        .bind(question_id)
+        .map(|row: PgRow| Answer {
+            id: AnswerId(row.get("id")),
+            content: row.get("content"),
+            question_id: row.get("corresponding_question"),
+            author: Some(Author {
+                id: AccountId(row.get("account_id")),
+                display_name: row.get("display_name"),
+            }),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn is_answer_owner(
+        &self,
+        answer_id: i32,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query("SELECT * from answers where id = $1 and account_id = $2")
+            .bind(answer_id)
+            .bind(account_id.0)
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(answer) => Ok(answer.is_some()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Ownership is checked by the caller, as moderators may edit any answer
+    pub async fn update_answer(self, answer: Answer, id: i32) -> Result<Answer, Error> {
+        match sqlx::query(
+            "WITH answer AS (UPDATE answers SET content = $1
+        WHERE id = $2
+        RETURNING id, content, corresponding_question, account_id)
+        SELECT answer.*, accounts.display_name FROM answer
+        LEFT JOIN accounts ON accounts.id = answer.account_id",
+        )
+        .bind(answer.content)
+        .bind(id)
+        .map(|row: PgRow| Answer {
+            id: AnswerId(row.get("id")),
+            content: row.get("content"),
+            question_id: row.get("corresponding_question"),
+            author: Some(Author {
+                id: AccountId(row.get("account_id")),
+                display_name: row.get("display_name"),
+            }),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(answer) => Ok(answer),
             Err(error) => {
-                tracing::event!(
-                    tracing::Level::ERROR,
-                    code = error
-                        .as_database_error()
-                        .unwrap()
-                        .code()
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap(),
-                    db_message = error.as_database_error().unwrap().message(),
-                    constraint = error.as_database_error().unwrap().constraint().unwrap()
-                );
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
                 Err(Error::DatabaseQueryError(error))
             }
         }
     }
 
-    pub async fn add_account(self, account: Account) -> Result<bool, Error> {
-        match sqlx::query("INSERT INTO accounts (email, password) VALUES ($1, $2)")
-            .bind(account.email)
-            .bind(account.password)
+    /// Ownership is checked by the caller, as moderators may delete any answer
+    pub async fn delete_answer(self, id: i32) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM answers WHERE id = $1")
+            .bind(id)
             .execute(&self.connection)
             .await
         {
             Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn add_account(self, account: Account) -> Result<AccountId, Error> {
+        match sqlx::query(
+            "INSERT INTO accounts (email, password, verification_sent_at) VALUES ($1, $2, NOW())
+            RETURNING id",
+        )
+        .bind(account.email)
+        .bind(account.password)
+        .map(|row: PgRow| AccountId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(account_id) => Ok(account_id),
             Err(error) => {
-                tracing::event!(
-                    tracing::Level::ERROR,
-                    code = error
-                        .as_database_error()
-                        .unwrap()
-                        .code()
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap(),
-                    db_message = error.as_database_error().unwrap().message(),
-                    constraint = error.as_database_error().unwrap().constraint().unwrap()
-                );
+                log_database_error(&error);
                 Err(Error::DatabaseQueryError(error))
             }
         }
     }
 
     pub async fn get_account(self, email: String) -> Result<Account, Error> {
-        match sqlx::query("SELECT * from accounts where email = $1")
+        match sqlx::query("SELECT * from accounts where LOWER(email) = LOWER($1)")
+            .bind(email)
+            .map(|row: PgRow| Account {
+                id: Some(AccountId(row.get("id"))),
+                email: row.get("email"),
+                password: row.get("password"),
+                role: row.get::<String, _>("role").parse().unwrap_or_default(),
+            })
+            .fetch_one(&self.connection)
+            .await
+        {
+            Ok(account) => Ok(account),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Like `get_account`, but an unknown email is not an error
+    pub async fn find_account_by_email(&self, email: &str) -> Result<Option<Account>, Error> {
+        match sqlx::query("SELECT * from accounts where LOWER(email) = LOWER($1)")
             .bind(email)
             .map(|row: PgRow| Account {
                 id: Some(AccountId(row.get("id"))),
                 email: row.get("email"),
                 password: row.get("password"),
+                role: row.get::<String, _>("role").parse().unwrap_or_default(),
+            })
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(account) => Ok(account),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Account linked to the identity `subject` at the OpenID Connect provider `issuer`
+    pub async fn get_account_by_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<Account>, Error> {
+        match sqlx::query(
+            "SELECT accounts.* from account_identities
+            JOIN accounts ON accounts.id = account_identities.account_id
+            WHERE account_identities.issuer = $1 AND account_identities.subject = $2",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .map(|row: PgRow| Account {
+            id: Some(AccountId(row.get("id"))),
+            email: row.get("email"),
+            password: row.get("password"),
+            role: row.get::<String, _>("role").parse().unwrap_or_default(),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(account) => Ok(account),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Creates an account for a user who signed in with an OpenID Connect provider.
+    /// It has no password until the user sets one, `!` is never a valid hash.
+    pub async fn add_external_account(
+        &self,
+        email: &str,
+        email_verified: bool,
+    ) -> Result<AccountId, Error> {
+        match sqlx::query(
+            "INSERT INTO accounts (email, password, email_verified_at)
+            VALUES ($1, $2, CASE WHEN $3 THEN NOW() END)
+            RETURNING id",
+        )
+        .bind(email)
+        .bind(NO_PASSWORD)
+        .bind(email_verified)
+        .map(|row: PgRow| AccountId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(account_id) => Ok(account_id),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                match error.as_database_error() {
+                    Some(db_error) if db_error.is_unique_violation() => {
+                        Err(Error::EmailAlreadyInUse)
+                    }
+                    _ => Err(Error::DatabaseQueryError(error)),
+                }
+            }
+        }
+    }
+
+    pub async fn add_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "INSERT INTO account_identities (issuer, subject, account_id) VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Provider logins linked to the account, oldest first
+    pub async fn get_identities(
+        &self,
+        account_id: &AccountId,
+    ) -> Result<Vec<LinkedIdentity>, Error> {
+        match sqlx::query(
+            "SELECT issuer, subject, created_on from account_identities
+            WHERE account_id = $1
+            ORDER BY created_on",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| LinkedIdentity {
+            issuer: row.get("issuer"),
+            subject: row.get("subject"),
+            created_on: row.get("created_on"),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(identities) => Ok(identities),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn is_email_verified(&self, account_id: &AccountId) -> Result<bool, Error> {
+        match sqlx::query(
+            "SELECT email_verified_at IS NOT NULL AS verified from accounts where id = $1",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| row.get("verified"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(verified) => Ok(verified),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Marks the email as verified, as long as the account still uses
+    /// the address the verification link was sent to.
+    pub async fn verify_email(&self, account_id: &AccountId, email: &str) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $1 AND email = $2",
+        )
+        .bind(account_id.0)
+        .bind(email)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Records that a new verification email is about to be sent and returns
+    /// the address to send it to. Returns `None` while the last email is
+    /// younger than `interval` seconds.
+    pub async fn claim_verification_resend(
+        &self,
+        account_id: &AccountId,
+        interval: i64,
+    ) -> Result<Option<String>, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET verification_sent_at = NOW()
+            WHERE id = $1 AND email_verified_at IS NULL
+            AND (verification_sent_at IS NULL OR verification_sent_at < NOW() - make_interval(secs => $2))
+            RETURNING email",
+        )
+        .bind(account_id.0)
+        .bind(interval as f64)
+        .map(|row: PgRow| row.get("email"))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(email) => Ok(email),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn get_account_by_id(&self, account_id: &AccountId) -> Result<Account, Error> {
+        match sqlx::query("SELECT * from accounts where id = $1")
+            .bind(account_id.0)
+            .map(|row: PgRow| Account {
+                id: Some(AccountId(row.get("id"))),
+                email: row.get("email"),
+                password: row.get("password"),
+                role: row.get::<String, _>("role").parse().unwrap_or_default(),
             })
             .fetch_one(&self.connection)
             .await
@@ -220,4 +543,915 @@ impl Store {
             }
         }
     }
+
+    /// A session is valid as long as its account exists and the token
+    /// was issued after the sessions of the account were last revoked.
+    pub async fn is_session_valid(
+        &self,
+        account_id: &AccountId,
+        issued_at: Option<DateTime<Utc>>,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "SELECT id from accounts where id = $1
+            AND (sessions_revoked_at IS NULL OR sessions_revoked_at <= $2)",
+        )
+        .bind(account_id.0)
+        .bind(issued_at.map(|iat| iat.naive_utc()))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(account) => Ok(account.is_some()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Stores the new password hash and revokes every session issued before `revoked_at`
+    pub async fn update_password(
+        &self,
+        account_id: &AccountId,
+        password: String,
+        revoked_at: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET password = $1, sessions_revoked_at = $2 WHERE id = $3",
+        )
+        .bind(password)
+        .bind(revoked_at.naive_utc())
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Replaces the stored hash of an unchanged password, sessions stay valid
+    pub async fn update_password_hash(
+        &self,
+        account_id: &AccountId,
+        password: String,
+    ) -> Result<bool, Error> {
+        match sqlx::query("UPDATE accounts SET password = $1 WHERE id = $2")
+            .bind(password)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Changes the email of the account, which has to be verified again
+    pub async fn update_email(&self, account_id: &AccountId, email: &str) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET email = $1, email_verified_at = NULL, verification_sent_at = NOW()
+            WHERE id = $2",
+        )
+        .bind(email)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                match error.as_database_error() {
+                    Some(db_error) if db_error.is_unique_violation() => {
+                        Err(Error::EmailAlreadyInUse)
+                    }
+                    _ => Err(Error::DatabaseQueryError(error)),
+                }
+            }
+        }
+    }
+
+    /// Changes the role of an account and revokes its sessions, so the
+    /// new role is picked up with the next login
+    pub async fn update_role(
+        &self,
+        account_id: &AccountId,
+        role: Role,
+        revoked_at: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET role = $1, sessions_revoked_at = $2 WHERE id = $3 RETURNING id",
+        )
+        .bind(role.as_str())
+        .bind(revoked_at.naive_utc())
+        .bind(account_id.0)
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn get_profile(&self, account_id: &AccountId) -> Result<Profile, Error> {
+        match sqlx::query(
+            "SELECT id, display_name, bio, website, created_on from accounts where id = $1",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| Profile {
+            id: AccountId(row.get("id")),
+            display_name: row.get("display_name"),
+            bio: row.get("bio"),
+            website: row.get("website"),
+            created_on: row.get("created_on"),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(profile) => Ok(profile),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn update_profile(
+        &self,
+        account_id: &AccountId,
+        profile: ProfileUpdate,
+    ) -> Result<Profile, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET display_name = $1, bio = $2, website = $3 WHERE id = $4
+            RETURNING id, display_name, bio, website, created_on",
+        )
+        .bind(profile.display_name)
+        .bind(profile.bio)
+        .bind(profile.website)
+        .bind(account_id.0)
+        .map(|row: PgRow| Profile {
+            id: AccountId(row.get("id")),
+            display_name: row.get("display_name"),
+            bio: row.get("bio"),
+            website: row.get("website"),
+            created_on: row.get("created_on"),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(profile) => Ok(profile),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn get_questions_by_account(
+        &self,
+        account_id: &AccountId,
+    ) -> Result<Vec<Question>, Error> {
+        match sqlx::query(
+            "SELECT questions.*, accounts.display_name from questions
+            LEFT JOIN accounts ON accounts.id = questions.account_id
+            WHERE questions.account_id = $1
+            ORDER BY questions.created_on",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| Question {
+            id: QuestionId(row.get("id")),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            author: Some(Author {
+                id: AccountId(row.get("account_id")),
+                display_name: row.get("display_name"),
+            }),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn get_answers_by_account(
+        &self,
+        account_id: &AccountId,
+    ) -> Result<Vec<Answer>, Error> {
+        match sqlx::query(
+            "SELECT answers.*, accounts.display_name from answers
+            LEFT JOIN accounts ON accounts.id = answers.account_id
+            WHERE answers.account_id = $1
+            ORDER BY answers.created_on",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| Answer {
+            id: AnswerId(row.get("id")),
+            content: row.get("content"),
+            question_id: row.get("corresponding_question"),
+            author: Some(Author {
+                id: AccountId(row.get("account_id")),
+                display_name: row.get("display_name"),
+            }),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn get_email_verified_at(
+        &self,
+        account_id: &AccountId,
+    ) -> Result<Option<NaiveDateTime>, Error> {
+        match sqlx::query("SELECT email_verified_at from accounts where id = $1")
+            .bind(account_id.0)
+            .map(|row: PgRow| row.get("email_verified_at"))
+            .fetch_one(&self.connection)
+            .await
+        {
+            Ok(verified_at) => Ok(verified_at),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Deletes the account and hands its questions and answers over to the
+    /// "deleted user" placeholder, so nothing referencing the account breaks.
+    /// Its sessions die with it, as `auth()` requires the account to exist.
+    pub async fn delete_account(&self, account_id: &AccountId) -> Result<bool, Error> {
+        let result = async {
+            let mut tx = self.connection.begin().await?;
+
+            sqlx::query("UPDATE questions SET account_id = $1 WHERE account_id = $2")
+                .bind(DELETED_ACCOUNT_ID.0)
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("UPDATE answers SET account_id = $1 WHERE account_id = $2")
+                .bind(DELETED_ACCOUNT_ID.0)
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("DELETE FROM account_identities WHERE account_id = $1")
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("DELETE FROM personal_access_tokens WHERE account_id = $1")
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("DELETE FROM recovery_codes WHERE account_id = $1")
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("DELETE FROM sessions WHERE account_id = $1")
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("DELETE FROM accounts WHERE id = $1")
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await
+        }
+        .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn add_token(
+        &self,
+        account_id: &AccountId,
+        name: &str,
+        token_hash: &str,
+        scopes: &[Scope],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<TokenInfo, Error> {
+        match sqlx::query(
+            "INSERT INTO personal_access_tokens (account_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, scopes, created_on, last_used_at, expires_at",
+        )
+        .bind(account_id.0)
+        .bind(name)
+        .bind(token_hash)
+        .bind(scopes.iter().map(Scope::as_str).collect::<Vec<_>>())
+        .bind(expires_at.map(|exp| exp.naive_utc()))
+        .map(|row: PgRow| TokenInfo {
+            id: TokenId(row.get("id")),
+            name: row.get("name"),
+            scopes: row
+                .get::<Vec<String>, _>("scopes")
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+            created_on: row.get("created_on"),
+            last_used_at: row.get("last_used_at"),
+            expires_at: row.get("expires_at"),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(token) => Ok(token),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn get_tokens(&self, account_id: &AccountId) -> Result<Vec<TokenInfo>, Error> {
+        match sqlx::query(
+            "SELECT id, name, scopes, created_on, last_used_at, expires_at
+            from personal_access_tokens where account_id = $1 ORDER BY id",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| TokenInfo {
+            id: TokenId(row.get("id")),
+            name: row.get("name"),
+            scopes: row
+                .get::<Vec<String>, _>("scopes")
+                .iter()
+                .filter_map(|scope| scope.parse().ok())
+                .collect(),
+            created_on: row.get("created_on"),
+            last_used_at: row.get("last_used_at"),
+            expires_at: row.get("expires_at"),
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(tokens) => Ok(tokens),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Returns false if the account has no token with this id
+    pub async fn delete_token(
+        &self,
+        account_id: &AccountId,
+        token_id: &TokenId,
+    ) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM personal_access_tokens WHERE id = $1 AND account_id = $2")
+            .bind(token_id.0)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Looks up an unexpired personal access token and records its use.
+    /// The role is read from the account, so role changes apply right away.
+    pub async fn use_token(&self, token_hash: &str) -> Result<Option<Session>, Error> {
+        match sqlx::query(
+            "WITH used AS (
+                UPDATE personal_access_tokens SET last_used_at = NOW()
+                WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > NOW())
+                RETURNING account_id, scopes, expires_at
+            )
+            SELECT used.*, accounts.role from used
+            JOIN accounts ON accounts.id = used.account_id",
+        )
+        .bind(token_hash)
+        .map(|row: PgRow| Session {
+            exp: row
+                .get::<Option<NaiveDateTime>, _>("expires_at")
+                .map(|exp| Utc.from_utc_datetime(&exp))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            account_id: AccountId(row.get("account_id")),
+            iat: None,
+            role: row.get::<String, _>("role").parse().unwrap_or_default(),
+            sid: None,
+            scopes: Some(
+                row.get::<Vec<String>, _>("scopes")
+                    .iter()
+                    .filter_map(|scope| scope.parse().ok())
+                    .collect(),
+            ),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(session) => Ok(session),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn get_two_factor(&self, account_id: &AccountId) -> Result<TwoFactor, Error> {
+        match sqlx::query(
+            "SELECT totp_secret, totp_enabled_at IS NOT NULL AS enabled from accounts where id = $1",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| TwoFactor {
+            secret: row.get("totp_secret"),
+            enabled: row.get("enabled"),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(two_factor) => Ok(two_factor),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Stores the secret of a new setup, returns false if 2FA is already enabled
+    pub async fn set_totp_secret(
+        &self,
+        account_id: &AccountId,
+        secret: &[u8],
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET totp_secret = $1, totp_last_step = NULL
+            WHERE id = $2 AND totp_enabled_at IS NULL",
+        )
+        .bind(secret)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Enables the pending setup and replaces the recovery codes. `step` is
+    /// the time step of the confirmation code, which may not be used again.
+    pub async fn enable_two_factor(
+        &self,
+        account_id: &AccountId,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, Error> {
+        let result = async {
+            let mut tx = self.connection.begin().await?;
+
+            let enabled = sqlx::query(
+                "UPDATE accounts SET totp_enabled_at = NOW(), totp_last_step = $1
+                WHERE id = $2 AND totp_secret IS NOT NULL AND totp_enabled_at IS NULL",
+            )
+            .bind(step)
+            .bind(account_id.0)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                == 1;
+            if !enabled {
+                return Ok(false);
+            }
+
+            sqlx::query("DELETE FROM recovery_codes WHERE account_id = $1")
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query(
+                "INSERT INTO recovery_codes (account_id, code_hash)
+                SELECT $1, UNNEST($2::TEXT[])",
+            )
+            .bind(account_id.0)
+            .bind(recovery_code_hashes)
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await.map(|_| true)
+        }
+        .await;
+
+        match result {
+            Ok(enabled) => Ok(enabled),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Records the time step of a TOTP code, returns false if this
+    /// or a later code was already used
+    pub async fn use_totp_step(&self, account_id: &AccountId, step: i64) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET totp_last_step = $1
+            WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+        )
+        .bind(step)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Marks an unused recovery code as used, returns false if there is none
+    pub async fn use_recovery_code(
+        &self,
+        account_id: &AccountId,
+        code_hash: &str,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE recovery_codes SET used_at = NOW() WHERE id = (
+                SELECT id from recovery_codes
+                WHERE account_id = $1 AND code_hash = $2 AND used_at IS NULL
+                LIMIT 1
+            )",
+        )
+        .bind(account_id.0)
+        .bind(code_hash)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn disable_two_factor(&self, account_id: &AccountId) -> Result<bool, Error> {
+        let result = async {
+            let mut tx = self.connection.begin().await?;
+
+            sqlx::query(
+                "UPDATE accounts SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+                WHERE id = $1",
+            )
+            .bind(account_id.0)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query("DELETE FROM recovery_codes WHERE account_id = $1")
+                .bind(account_id.0)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await
+        }
+        .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Records a new session. Expired sessions of the account and those
+    /// revoked by a password or role change are cleaned up on the way.
+    pub async fn add_session(
+        &self,
+        session_id: &str,
+        account_id: &AccountId,
+        expires_at: DateTime<Utc>,
+        client: &ClientInfo,
+    ) -> Result<bool, Error> {
+        let result = async {
+            sqlx::query(
+                "DELETE FROM sessions USING accounts
+                WHERE sessions.account_id = $1 AND accounts.id = sessions.account_id
+                AND (sessions.expires_at < NOW() OR sessions.created_on < accounts.sessions_revoked_at)",
+            )
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await?;
+
+            sqlx::query(
+                "INSERT INTO sessions (id, account_id, expires_at, user_agent, ip_address)
+                VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(session_id)
+            .bind(account_id.0)
+            .bind(expires_at.naive_utc())
+            .bind(&client.user_agent)
+            .bind(client.addr.map(|addr| addr.ip().to_string()))
+            .execute(&self.connection)
+            .await
+        }
+        .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Updates the last activity of the session, returns false if it was signed out
+    pub async fn touch_session(
+        &self,
+        session_id: &str,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE sessions SET last_seen_at = NOW()
+            WHERE id = $1 AND account_id = $2 AND expires_at > NOW()",
+        )
+        .bind(session_id)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Active sessions of the account, most recently used first
+    pub async fn get_sessions(
+        &self,
+        account_id: &AccountId,
+        current: Option<&str>,
+    ) -> Result<Vec<SessionInfo>, Error> {
+        match sqlx::query(
+            "SELECT sessions.* from sessions
+            JOIN accounts ON accounts.id = sessions.account_id
+            WHERE sessions.account_id = $1 AND sessions.expires_at > NOW()
+            AND (accounts.sessions_revoked_at IS NULL OR sessions.created_on >= accounts.sessions_revoked_at)
+            ORDER BY sessions.last_seen_at DESC",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| {
+            let id: String = row.get("id");
+            SessionInfo {
+                current: current == Some(id.as_str()),
+                id,
+                created_on: row.get("created_on"),
+                last_seen_at: row.get("last_seen_at"),
+                expires_at: row.get("expires_at"),
+                user_agent: row.get("user_agent"),
+                ip_address: row.get("ip_address"),
+            }
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(sessions) => Ok(sessions),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Returns false if the account has no session with this id
+    pub async fn delete_session(
+        &self,
+        account_id: &AccountId,
+        session_id: &str,
+    ) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM sessions WHERE id = $1 AND account_id = $2")
+            .bind(session_id)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Seconds until the next verification email may be sent
+    pub async fn verification_resend_wait(
+        &self,
+        account_id: &AccountId,
+        interval: i64,
+    ) -> Result<u64, Error> {
+        match sqlx::query(
+            "SELECT CEIL(EXTRACT(EPOCH FROM verification_sent_at + make_interval(secs => $2) - NOW()))::BIGINT AS wait
+            from accounts where id = $1",
+        )
+        .bind(account_id.0)
+        .bind(interval as f64)
+        .map(|row: PgRow| row.get::<Option<i64>, _>("wait"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(wait) => Ok(wait.unwrap_or(0).max(1) as u64),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Whether the database answers at all
+    pub async fn ping(&self) -> Result<bool, Error> {
+        match sqlx::query("SELECT 1").execute(&self.connection).await {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Queues content for review, content queued already keeps its place
+    pub async fn queue_review(
+        &self,
+        kind: ContentKind,
+        content_id: i32,
+        request_id: &str,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "INSERT INTO moderation_reviews (content_type, content_id, request_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (content_type, content_id) DO NOTHING",
+        )
+        .bind(kind.as_str())
+        .bind(content_id)
+        .bind(request_id)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Content waiting for review, oldest first
+    pub async fn get_reviews(&self) -> Result<Vec<Review>, Error> {
+        match sqlx::query("SELECT * from moderation_reviews ORDER BY queued_at, id")
+            .map(|row: PgRow| Review {
+                id: row.get("id"),
+                content_type: row.get("content_type"),
+                content_id: row.get("content_id"),
+                request_id: row.get("request_id"),
+                queued_at: row.get("queued_at"),
+            })
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(reviews) => Ok(reviews),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Censored content cached under the hash, unless older than the TTL
+    pub async fn get_cached_moderation(
+        &self,
+        content_hash: &str,
+        ttl_seconds: u64,
+    ) -> Result<Option<(String, u64)>, Error> {
+        match sqlx::query(
+            "SELECT censored,
+            FLOOR(EXTRACT(EPOCH FROM cached_at + make_interval(secs => $2) - NOW()))::BIGINT AS remaining
+            from moderation_cache
+            WHERE content_hash = $1 AND cached_at > NOW() - make_interval(secs => $2)",
+        )
+        .bind(content_hash)
+        .bind(ttl_seconds as f64)
+        .map(|row: PgRow| {
+            let remaining: i64 = row.get("remaining");
+            (row.get("censored"), remaining.max(0) as u64)
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(cached) => Ok(cached),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    pub async fn cache_moderation(
+        &self,
+        content_hash: &str,
+        censored: &str,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "INSERT INTO moderation_cache (content_hash, censored) VALUES ($1, $2)
+            ON CONFLICT (content_hash) DO UPDATE SET censored = $2, cached_at = NOW()",
+        )
+        .bind(content_hash)
+        .bind(censored)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Returns the number of deleted entries
+    pub async fn delete_expired_moderations(&self, ttl_seconds: u64) -> Result<u64, Error> {
+        match sqlx::query(
+            "DELETE FROM moderation_cache WHERE cached_at < NOW() - make_interval(secs => $1)",
+        )
+        .bind(ttl_seconds as f64)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+
+    /// Returns false if no review with this id is queued
+    pub async fn delete_review(&self, id: i32) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM moderation_reviews WHERE id = $1")
+            .bind(id)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() == 1),
+            Err(error) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", error);
+                Err(Error::DatabaseQueryError(error))
+            }
+        }
+    }
+}
+
+/// Logs the details the database reported. Errors like pool timeouts do not
+/// come from the database and have none of them.
+fn log_database_error(error: &sqlx::Error) {
+    match error.as_database_error() {
+        Some(db_error) => tracing::event!(
+            tracing::Level::ERROR,
+            code = db_error.code().as_deref(),
+            db_message = db_error.message(),
+            constraint = db_error.constraint()
+        ),
+        None => tracing::event!(tracing::Level::ERROR, "{:?}", error),
+    }
 }
@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::{event, Level};
+
+use handle_errors::Error;
+
+use crate::config::Config;
+
+/// Entries without a failed attempt for this long are forgotten
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Stale entries are only swept once the map grows beyond this size
+const SWEEP_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Tracks failed logins per email and per IP address. Once a key exceeds
+/// its allowed attempts it is locked out, and every further failure doubles
+/// the lockout up to `max_lockout`.
+#[derive(Debug, Clone)]
+pub struct LoginThrottle {
+    attempts: Arc<Mutex<HashMap<String, Attempts>>>,
+    max_attempts_per_email: u32,
+    max_attempts_per_ip: u32,
+    lockout: Duration,
+    max_lockout: Duration,
+}
+
+impl LoginThrottle {
+    pub fn new(config: &Config) -> Self {
+        LoginThrottle {
+            attempts: Arc::new(Mutex::new(HashMap::new())),
+            max_attempts_per_email: config.login_max_attempts,
+            max_attempts_per_ip: config.login_max_attempts_per_ip,
+            lockout: Duration::from_secs(config.login_lockout_seconds),
+            max_lockout: Duration::from_secs(config.login_max_lockout_seconds),
+        }
+    }
+
+    pub fn keys(email: &str, addr: Option<SocketAddr>) -> Vec<String> {
+        let mut keys = vec![format!("email:{}", email.trim().to_lowercase())];
+        if let Some(addr) = addr {
+            keys.push(format!("ip:{}", addr.ip()));
+        }
+        keys
+    }
+
+    /// Rejects the attempt while any of the keys is locked out
+    pub fn check(&self, keys: &[String]) -> Result<(), Error> {
+        let now = Instant::now();
+        let attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+
+        let retry_after = keys
+            .iter()
+            .filter_map(|key| attempts.get(key).and_then(|a| a.locked_until))
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max();
+
+        match retry_after {
+            // Round up, so clients never retry a moment too early
+            Some(wait) => Err(Error::TooManyRequests(wait.as_secs() + 1)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, keys: &[String]) {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+
+        if attempts.len() > SWEEP_THRESHOLD {
+            attempts.retain(|_, a| now.duration_since(a.last_failure) < FORGET_AFTER);
+        }
+
+        for key in keys {
+            let entry = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            if now.duration_since(entry.last_failure) >= FORGET_AFTER {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.last_failure = now;
+
+            let max_attempts = if key.starts_with("ip:") {
+                self.max_attempts_per_ip
+            } else {
+                self.max_attempts_per_email
+            };
+
+            if let Some(lockout) = self.lockout_for(entry.failures, max_attempts) {
+                entry.locked_until = Some(now + lockout);
+                event!(
+                    target: "audit",
+                    Level::WARN,
+                    key = key.as_str(),
+                    failures = entry.failures,
+                    lockout_seconds = lockout.as_secs(),
+                    "login locked out"
+                );
+            }
+        }
+    }
+
+    /// A successful login forgets the failures of the account. The IP address
+    /// keeps its history, otherwise an attacker could reset it with an own account.
+    pub fn record_success(&self, email: &str) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        attempts.remove(&format!("email:{}", email.trim().to_lowercase()));
+    }
+
+    fn lockout_for(&self, failures: u32, max_attempts: u32) -> Option<Duration> {
+        if failures < max_attempts {
+            return None;
+        }
+
+        let doublings = (failures - max_attempts).min(16);
+        Some((self.lockout * 2u32.pow(doublings)).min(self.max_lockout))
+    }
+}
+
+#[cfg(test)]
+mod throttle_tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            attempts: Arc::new(Mutex::new(HashMap::new())),
+            max_attempts_per_email: 3,
+            max_attempts_per_ip: 10,
+            lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(300),
+        }
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_maximum() {
+        let throttle = throttle();
+
+        assert_eq!(throttle.lockout_for(2, 3), None);
+        assert_eq!(throttle.lockout_for(3, 3), Some(Duration::from_secs(30)));
+        assert_eq!(throttle.lockout_for(4, 3), Some(Duration::from_secs(60)));
+        assert_eq!(throttle.lockout_for(5, 3), Some(Duration::from_secs(120)));
+        assert_eq!(throttle.lockout_for(9, 3), Some(Duration::from_secs(300)));
+        assert_eq!(
+            throttle.lockout_for(1000, 3),
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn locks_out_after_max_attempts() {
+        let throttle = throttle();
+        let keys = LoginThrottle::keys("Test@Email.com", None);
+
+        for _ in 0..2 {
+            throttle.record_failure(&keys);
+            assert!(throttle.check(&keys).is_ok());
+        }
+
+        throttle.record_failure(&keys);
+        match throttle.check(&keys) {
+            Err(Error::TooManyRequests(retry_after)) => assert!(retry_after <= 31),
+            _ => panic!("Expected the login to be locked out"),
+        }
+
+        // Other spellings of the same email share the lockout
+        assert!(throttle
+            .check(&LoginThrottle::keys("test@email.com", None))
+            .is_err());
+    }
+
+    #[test]
+    fn success_resets_the_email_only() {
+        let throttle = throttle();
+        let addr = "127.0.0.1:1234".parse().ok();
+        let keys = LoginThrottle::keys("test@email.com", addr);
+
+        throttle.record_failure(&keys);
+        throttle.record_failure(&keys);
+        throttle.record_success("test@email.com");
+        throttle.record_failure(&keys);
+
+        assert!(throttle.check(&keys).is_ok());
+
+        let attempts = throttle.attempts.lock().unwrap();
+        assert_eq!(attempts.get("email:test@email.com").unwrap().failures, 1);
+        assert_eq!(attempts.get("ip:127.0.0.1").unwrap().failures, 3);
+    }
+}
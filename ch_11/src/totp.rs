@@ -0,0 +1,173 @@
+use rand::Rng;
+use ring::hmac;
+
+/// Parameters every authenticator app understands (RFC 6238 defaults)
+const SECRET_LENGTH: usize = 20;
+const DIGITS: u32 = 6;
+const PERIOD_SECONDS: i64 = 30;
+/// Also accept the previous and next code, to make up for clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Two groups of five base32 characters, 50 random bits per code
+const RECOVERY_CODE_GROUP_LENGTH: usize = 5;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    rand::thread_rng().gen::<[u8; SECRET_LENGTH]>().to_vec()
+}
+
+/// URI for the QR code scanned by authenticator apps. Spaces are encoded as
+/// `%20`, several apps show a `+` literally.
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        base32_encode(secret),
+        percent_encode(issuer),
+        DIGITS,
+        PERIOD_SECONDS
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Returns the time step the code belongs to, so it can be rejected when replayed
+pub fn verify(secret: &[u8], code: &str, unix_time: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let current = unix_time.div_euclid(PERIOD_SECONDS);
+
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS).find(|&step| {
+        let expected = format!("{:0width$}", code_at(secret, step), width = DIGITS as usize);
+        ring::constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok()
+    })
+}
+
+/// HOTP value for the counter (RFC 4226, section 5.3)
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let hash = tag.as_ref();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+/// One-time codes for when the authenticator is lost, shown once on enrollment
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut group = || {
+                (0..RECOVERY_CODE_GROUP_LENGTH)
+                    .map(|_| BASE32_ALPHABET[rng.gen_range(0..BASE32_ALPHABET.len())] as char)
+                    .collect::<String>()
+            };
+            format!("{}-{}", group(), group())
+        })
+        .collect()
+}
+
+/// Recovery codes are random enough for a plain SHA-256. They are compared
+/// without dashes and case, as users tend to type them differently.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_uppercase();
+    let digest = ring::digest::digest(&ring::digest::SHA256, normalized.as_bytes());
+    base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Unpadded base32 (RFC 4648), the encoding of secrets in otpauth URIs
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod totp_tests {
+    use super::*;
+
+    #[test]
+    fn matches_rfc_test_vectors() {
+        // RFC 6238, Appendix B (SHA1), truncated to six digits
+        let secret = b"12345678901234567890";
+        assert_eq!(code_at(secret, 59 / 30), 287082);
+        assert_eq!(code_at(secret, 1111111109 / 30), 81804);
+
+        assert_eq!(verify(secret, "287082", 59), Some(1));
+        assert_eq!(verify(secret, "287082", 59 + 30), Some(1));
+        assert_eq!(verify(secret, "287082", 59 + 60), None);
+        assert_eq!(verify(secret, "081804", 1111111109), Some(1111111109 / 30));
+        assert_eq!(verify(secret, "81804", 1111111109), None);
+
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_encode(secret), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), 11);
+
+        assert_eq!(
+            hash_recovery_code("abcde-fghij"),
+            hash_recovery_code(" ABCDEFGHIJ ")
+        );
+        assert_ne!(
+            hash_recovery_code("ABCDE-FGHIJ"),
+            hash_recovery_code("ABCDE-FGHIK")
+        );
+    }
+
+    #[test]
+    fn builds_otpauth_uri() {
+        let uri = otpauth_uri("Q&A Service", "bob@example.com", b"12345678901234567890");
+
+        assert_eq!(
+            uri,
+            "otpauth://totp/Q%26A%20Service:bob%40example.com\
+            ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Q%26A%20Service\
+            &algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
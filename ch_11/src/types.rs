@@ -1,4 +1,9 @@
 pub mod account;
 pub mod answer;
+pub mod health;
+pub mod moderation;
 pub mod pagination;
 pub mod question;
+pub mod session;
+pub mod token;
+pub mod two_factor;
@@ -1,18 +1,183 @@
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::types::{
+    answer::Answer,
+    question::Question,
+    session::SessionInfo,
+    token::{Scope, TokenInfo},
+};
+use crate::validation::{http_url, normalized_option};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Session {
     pub exp: DateTime<Utc>,
     pub account_id: AccountId,
+    #[serde(default)]
+    pub iat: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub role: Role,
+    /// Id of the server-side session, missing in tokens issued before
+    /// sessions were tracked and in personal access tokens
+    #[serde(default)]
+    pub sid: Option<String>,
+    /// Set for personal access tokens, which are limited to these scopes.
+    /// Never read from a token, sessions from `/login` may do everything.
+    #[serde(skip)]
+    pub scopes: Option<Vec<Scope>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Claims of the token embedded in email verification links
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmailVerification {
+    pub exp: DateTime<Utc>,
+    pub account_id: AccountId,
+    pub email: String,
+}
+
+/// Only bounded here, registration checks the email and password policy
+/// itself, and logins must not reveal which rules an old password breaks
+#[derive(Serialize, Deserialize, Debug, Clone, Validate)]
 pub struct Account {
     pub id: Option<AccountId>,
+    #[validate(length(max = 254, code = "too_long"))]
     pub email: String,
+    #[validate(length(max = 1024, code = "too_long"))]
     pub password: String,
+    /// Roles are only ever granted by an admin, never through a request body
+    #[serde(skip_deserializing)]
+    pub role: Role,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct AccountId(pub i32);
+
+/// Placeholder account which takes over the questions and answers of deleted accounts
+pub const DELETED_ACCOUNT_ID: AccountId = AccountId(0);
+
+/// Password of accounts created through an OpenID Connect login, no hash matches it
+pub const NO_PASSWORD: &str = "!";
+
+/// Roles are ordered, every role has the permissions of the roles below it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role: {}", s)),
+        }
+    }
+}
+
+/// Public view of an account, which never includes the email address
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    pub id: AccountId,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    pub created_on: NaiveDateTime,
+}
+
+/// Display name and website have to fit into their `VARCHAR` columns,
+/// the bio is bounded to keep profile pages manageable
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct ProfileUpdate {
+    #[serde(default, deserialize_with = "normalized_option")]
+    #[validate(length(max = 64, code = "too_long"))]
+    pub display_name: Option<String>,
+    #[serde(default, deserialize_with = "normalized_option")]
+    #[validate(length(max = 2000, code = "too_long"))]
+    pub bio: Option<String>,
+    #[serde(default, deserialize_with = "normalized_option")]
+    #[validate(length(max = 255, code = "too_long"), custom = "http_url")]
+    pub website: Option<String>,
+}
+
+/// Lightweight summary of the author of a question or answer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Author {
+    pub id: AccountId,
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct RoleChange {
+    pub role: Role,
+}
+
+/// Only bounded here like `Account`, the new password is checked against the policy
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct PasswordChange {
+    #[validate(length(max = 1024, code = "too_long"))]
+    pub current_password: String,
+    #[validate(length(max = 1024, code = "too_long"))]
+    pub new_password: String,
+}
+
+/// Only bounded here, the new email is normalized and checked by the route
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct EmailChange {
+    #[validate(length(max = 1024, code = "too_long"))]
+    pub password: String,
+    #[validate(length(max = 254, code = "too_long"))]
+    pub email: String,
+}
+
+/// Accounts without a password confirm by a recent sign-in at their provider instead
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct AccountDeletion {
+    #[serde(default)]
+    #[validate(length(max = 1024, code = "too_long"))]
+    pub password: String,
+}
+
+/// Login at an OpenID Connect provider linked to an account
+#[derive(Serialize, Debug, Clone)]
+pub struct LinkedIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub created_on: NaiveDateTime,
+}
+
+/// Everything we store about an account, handed out on request of its owner
+#[derive(Serialize, Debug, Clone)]
+pub struct AccountExport {
+    pub email: String,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub role: Role,
+    pub profile: Profile,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Answer>,
+    pub identities: Vec<LinkedIdentity>,
+    /// Personal access tokens, without the tokens themselves
+    pub tokens: Vec<TokenInfo>,
+    /// The TOTP secret and recovery codes stay secret
+    pub two_factor_enabled: bool,
+    /// Signed-in clients, with their user agent and IP address
+    pub sessions: Vec<SessionInfo>,
+    pub exported_at: DateTime<Utc>,
+}
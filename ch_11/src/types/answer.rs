@@ -1,7 +1,29 @@
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::types::account::Author;
+use crate::validation::{non_empty, normalized};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Validate)]
 pub struct Answer {
+    pub id: AnswerId,
+    #[serde(deserialize_with = "normalized")]
+    #[validate(custom = "non_empty", length(max = 10000, code = "too_long"))]
+    pub content: String,
+    pub question_id: i32,
+    #[serde(default, skip_deserializing)]
+    pub author: Option<Author>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct AnswerId(pub i32);
+
+/// Sent as form, with the question in the `questionId` field
+#[derive(Serialize, Deserialize, Debug, Clone, Validate)]
+pub struct NewAnswer {
+    #[serde(deserialize_with = "normalized")]
+    #[validate(custom = "non_empty", length(max = 10000, code = "too_long"))]
     pub content: String,
+    #[serde(rename = "questionId")]
     pub question_id: i32,
 }
@@ -0,0 +1,20 @@
+use serde::Serialize;
+
+use crate::types::moderation::ModerationHealth;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    /// Requests are served, but some of them are handled by a fallback
+    Degraded,
+    Unavailable,
+}
+
+/// Response of `/health`
+#[derive(Serialize, Debug, Clone)]
+pub struct Health {
+    pub status: HealthStatus,
+    pub database: HealthStatus,
+    pub moderation: ModerationHealth,
+}
@@ -0,0 +1,41 @@
+use chrono::prelude::*;
+use serde::Serialize;
+
+use crate::circuit_breaker::CircuitState;
+
+/// What a moderation review refers to
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    Question,
+    Answer,
+}
+
+impl ContentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentKind::Question => "question",
+            ContentKind::Answer => "answer",
+        }
+    }
+}
+
+/// Content stored uncensored while the moderator was unavailable,
+/// as listed under `/moderation/reviews`
+#[derive(Serialize, Debug, Clone)]
+pub struct Review {
+    pub id: i32,
+    pub content_type: String,
+    pub content_id: i32,
+    /// Request which stored the content, to find it in the logs
+    pub request_id: Option<String>,
+    pub queued_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModerationHealth {
+    pub provider: String,
+    pub failure_policy: String,
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+}
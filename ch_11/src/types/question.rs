@@ -1,19 +1,39 @@
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+use crate::types::account::Author;
+use crate::validation::{non_empty, normalized, normalized_tags, valid_tags};
+
+/// The title has to fit into its `VARCHAR(255)` column, content and tags
+/// are bounded to keep pages and the tag index manageable
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 pub struct Question {
     pub id: QuestionId,
+    #[serde(deserialize_with = "normalized")]
+    #[validate(custom = "non_empty", length(max = 255, code = "too_long"))]
     pub title: String,
+    #[serde(deserialize_with = "normalized")]
+    #[validate(custom = "non_empty", length(max = 10000, code = "too_long"))]
     pub content: String,
+    #[serde(default, deserialize_with = "normalized_tags")]
+    #[validate(length(max = 10, code = "too_many_tags"), custom = "valid_tags")]
     pub tags: Option<Vec<String>>,
+    #[serde(default, skip_deserializing)]
+    pub author: Option<Author>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct QuestionId(pub i32);
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 pub struct NewQuestion {
+    #[serde(deserialize_with = "normalized")]
+    #[validate(custom = "non_empty", length(max = 255, code = "too_long"))]
     pub title: String,
+    #[serde(deserialize_with = "normalized")]
+    #[validate(custom = "non_empty", length(max = 10000, code = "too_long"))]
     pub content: String,
+    #[serde(default, deserialize_with = "normalized_tags")]
+    #[validate(length(max = 10, code = "too_many_tags"), custom = "valid_tags")]
     pub tags: Option<Vec<String>>,
 }
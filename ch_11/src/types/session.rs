@@ -0,0 +1,23 @@
+use chrono::prelude::*;
+use serde::Serialize;
+use std::net::SocketAddr;
+
+/// Where a login came from, recorded with the session
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub addr: Option<SocketAddr>,
+}
+
+/// A signed-in client as listed under `/me/sessions`
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_on: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// The session of the request listing the sessions
+    pub current: bool,
+}
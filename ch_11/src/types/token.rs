@@ -0,0 +1,70 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::validation::{in_future, non_empty, non_empty_scopes, normalized};
+
+/// What a personal access token may do. Sessions from `/login` are not scoped.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    #[serde(rename = "questions:write")]
+    QuestionsWrite,
+    #[serde(rename = "answers:write")]
+    AnswersWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::QuestionsWrite => "questions:write",
+            Scope::AnswersWrite => "answers:write",
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "questions:write" => Ok(Scope::QuestionsWrite),
+            "answers:write" => Ok(Scope::AnswersWrite),
+            _ => Err(format!("Unknown scope: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenId(pub i32);
+
+/// The name is bounded to keep token lists readable
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct NewToken {
+    #[serde(deserialize_with = "normalized")]
+    #[validate(custom = "non_empty", length(max = 100, code = "too_long"))]
+    pub name: String,
+    #[validate(custom = "non_empty_scopes")]
+    pub scopes: Vec<Scope>,
+    /// Tokens without expiry stay valid until they are revoked
+    #[validate(custom = "in_future")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Stored details of a personal access token, never including the token itself
+#[derive(Serialize, Debug, Clone)]
+pub struct TokenInfo {
+    pub id: TokenId,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_on: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Response to creating a token, the only time the token is shown
+#[derive(Serialize, Debug, Clone)]
+pub struct CreatedToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: TokenInfo,
+}
@@ -0,0 +1,55 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::types::account::AccountId;
+
+/// Two-factor state of an account. A secret without `enabled` belongs
+/// to a setup which has not been confirmed with a code yet.
+#[derive(Debug, Clone)]
+pub struct TwoFactor {
+    pub secret: Option<Vec<u8>>,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TwoFactorSetup {
+    /// Base32 encoded, for users who cannot scan the QR code
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct TwoFactorCode {
+    #[validate(length(max = 32, code = "too_long"))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct TwoFactorDisable {
+    #[validate(length(max = 1024, code = "too_long"))]
+    pub password: String,
+    /// A TOTP code or one of the recovery codes
+    #[validate(length(max = 32, code = "too_long"))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TwoFactorLogin {
+    pub challenge_token: String,
+    /// A TOTP code or one of the recovery codes
+    pub code: String,
+}
+
+/// Claims of the challenge token `/login` hands out instead of a session
+/// when the account has two-factor authentication enabled
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TwoFactorChallenge {
+    pub exp: DateTime<Utc>,
+    pub account_id: AccountId,
+}
@@ -0,0 +1,394 @@
+use std::{borrow::Cow, collections::HashSet, fs, sync::Arc};
+
+use chrono::{DateTime, Utc};
+
+use email_address::{EmailAddress, Options};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use unicode_normalization::UnicodeNormalization;
+use validator::{Validate, ValidationError, ValidationErrors};
+use warp::{Filter, Rejection};
+
+use handle_errors::{Error, FieldError};
+
+use crate::config::Config;
+use crate::types::token::Scope;
+
+/// Longest address which still fits into an SMTP path (RFC 5321)
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_TAG_LENGTH: usize = 32;
+
+/// Deserializes a JSON body and rejects it with every rule it violates,
+/// the rules are declared on the type with `#[validate(...)]`
+pub fn validated_json<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Validate + Send,
+{
+    warp::body::json().and_then(validate)
+}
+
+/// Like `validated_json`, for `application/x-www-form-urlencoded` bodies
+pub fn validated_form<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Validate + Send,
+{
+    warp::body::form().and_then(validate)
+}
+
+async fn validate<T: Validate>(input: T) -> Result<T, Rejection> {
+    match input.validate() {
+        Ok(()) => Ok(input),
+        Err(errors) => Err(warp::reject::custom(Error::ValidationError(field_errors(
+            &errors,
+        )))),
+    }
+}
+
+fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields = errors.field_errors().into_iter().collect::<Vec<_>>();
+    fields.sort_by_key(|(field, _)| *field);
+
+    fields
+        .into_iter()
+        .flat_map(|(field, errors)| errors.iter().map(move |error| field_error(field, error)))
+        .collect()
+}
+
+/// The code of a rule names its catalog message, `field_<code>`. The rule
+/// parameters, except the rejected value, are the message arguments.
+fn field_error(field: &str, error: &ValidationError) -> FieldError {
+    let args = error
+        .params
+        .iter()
+        .filter(|(name, _)| *name != "value")
+        .map(|(name, value)| {
+            let value = value
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| value.to_string());
+            (name.as_ref(), value)
+        })
+        .collect::<Vec<_>>();
+
+    FieldError::localized(field, &format!("field_{}", error.code), &args)
+}
+
+/// Text is stored in NFC and without surrounding whitespace, so visually
+/// identical input is stored and compared the same way
+pub fn normalize_text(text: &str) -> String {
+    text.trim().nfc().collect()
+}
+
+/// `deserialize_with` for text fields, see `normalize_text`
+pub fn normalized<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|text| normalize_text(&text))
+}
+
+/// `deserialize_with` for optional text fields. Blank text clears the field.
+pub fn normalized_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(|text| {
+        text.map(|text| normalize_text(&text))
+            .filter(|text| !text.is_empty())
+    })
+}
+
+/// `deserialize_with` for tag lists. Tags are also lowercased, `Rust` and `rust` are the same tag.
+pub fn normalized_tags<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    Option::<Vec<String>>::deserialize(deserializer).map(|tags| {
+        tags.map(|tags| {
+            tags.iter()
+                .map(|tag| normalize_text(tag).to_lowercase())
+                .collect()
+        })
+    })
+}
+
+pub fn non_empty(text: &str) -> Result<(), ValidationError> {
+    if text.is_empty() {
+        return Err(validation_error("empty", &[]));
+    }
+    Ok(())
+}
+
+/// Tags are short words like `rust`, `c++` or `asp.net`
+pub fn valid_tags(tags: &[String]) -> Result<(), ValidationError> {
+    for tag in tags {
+        let length = tag.chars().count();
+        if length == 0 || length > MAX_TAG_LENGTH {
+            return Err(validation_error(
+                "tag_length",
+                &[("max", MAX_TAG_LENGTH.to_string())],
+            ));
+        }
+        if !tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '.' | '+' | '#'))
+        {
+            return Err(validation_error("tag_format", &[("tag", tag.clone())]));
+        }
+    }
+    Ok(())
+}
+
+pub fn non_empty_scopes(scopes: &[Scope]) -> Result<(), ValidationError> {
+    if scopes.is_empty() {
+        return Err(validation_error("no_scopes", &[]));
+    }
+    Ok(())
+}
+
+pub fn in_future(time: &DateTime<Utc>) -> Result<(), ValidationError> {
+    if *time <= Utc::now() {
+        return Err(validation_error("not_in_future", &[]));
+    }
+    Ok(())
+}
+
+/// Links shown to other users must not run scripts, e.g. with `javascript:`
+pub fn http_url(url: &str) -> Result<(), ValidationError> {
+    match reqwest::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+        _ => Err(validation_error("http_url", &[])),
+    }
+}
+
+fn validation_error(code: &'static str, params: &[(&'static str, String)]) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    for (name, value) in params {
+        error.add_param(Cow::Borrowed(*name), value);
+    }
+    error
+}
+
+/// Emails are stored and compared in this form, so `Bob@x.com`
+/// and `bob@x.com` always refer to the same account
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub fn validate_email(field: &str, email: &str) -> Option<FieldError> {
+    if email.is_empty() {
+        return Some(FieldError::localized(field, "field_empty", &[]));
+    }
+    if email.len() > MAX_EMAIL_LENGTH {
+        return Some(FieldError::localized(
+            field,
+            "field_too_long",
+            &[("max", MAX_EMAIL_LENGTH.to_string())],
+        ));
+    }
+
+    let options = Options::default()
+        .without_display_text()
+        .without_domain_literal();
+    match EmailAddress::parse_with_options(email, options) {
+        Ok(_) => None,
+        Err(_) => Some(FieldError::localized(field, "field_invalid_email", &[])),
+    }
+}
+
+/// Rules every new password has to follow. The breached passwords are
+/// loaded once at startup and shared between all clones of the policy.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    breached: Arc<HashSet<String>>,
+}
+
+impl PasswordPolicy {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let breached = match &config.breached_passwords_file {
+            Some(path) => {
+                let content = fs::read(path).map_err(Error::IoError)?;
+                String::from_utf8_lossy(&content)
+                    .lines()
+                    .map(|line| line.trim_end_matches('\r'))
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+            None => HashSet::new(),
+        };
+
+        tracing::event!(
+            tracing::Level::INFO,
+            breached_passwords = breached.len(),
+            "password policy loaded"
+        );
+
+        Ok(PasswordPolicy {
+            min_length: config.password_min_length,
+            max_length: config.password_max_length,
+            breached: Arc::new(breached),
+        })
+    }
+
+    /// Returns every rule the password violates
+    pub fn check(&self, field: &str, password: &str) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            errors.push(FieldError::localized(
+                field,
+                "field_too_short",
+                &[("min", self.min_length.to_string())],
+            ));
+        }
+        if length > self.max_length {
+            errors.push(FieldError::localized(
+                field,
+                "field_too_long",
+                &[("max", self.max_length.to_string())],
+            ));
+        }
+        if self.breached.contains(password) {
+            errors.push(FieldError::localized(field, "field_breached_password", &[]));
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::*;
+    use crate::types::{account::ProfileUpdate, question::NewQuestion, token::NewToken};
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            breached: Arc::new(HashSet::from(["password123".to_string()])),
+        }
+    }
+
+    #[test]
+    fn normalizes_and_validates_emails() {
+        assert_eq!(normalize_email("  Bob@Example.COM "), "bob@example.com");
+
+        assert_eq!(validate_email("email", "bob@example.com"), None);
+        assert!(validate_email("email", "").is_some());
+        assert!(validate_email("email", "bob").is_some());
+        assert!(validate_email("email", "bob@").is_some());
+        assert!(validate_email("email", "Bob <bob@example.com>").is_some());
+        assert!(validate_email("email", &format!("{}@example.com", "a".repeat(250))).is_some());
+    }
+
+    #[test]
+    fn password_policy_lists_every_violation() {
+        let policy = policy();
+
+        assert!(policy.check("password", "correct horse").is_empty());
+        assert_eq!(policy.check("password", "short").len(), 1);
+        assert_eq!(policy.check("password", &"x".repeat(17)).len(), 1);
+        assert_eq!(
+            policy.check("password", "password123"),
+            vec![FieldError::new(
+                "password",
+                "appeared in a data breach, please choose another one"
+            )]
+        );
+    }
+
+    #[test]
+    fn normalizes_and_validates_questions() {
+        let question = serde_json::from_value::<NewQuestion>(serde_json::json!({
+            "title": "  Cafe\u{301} crashes ",
+            "content": "Why?",
+            "tags": [" Rust ", "C++"],
+        }))
+        .unwrap();
+        assert_eq!(question.title, "Caf\u{e9} crashes");
+        assert_eq!(
+            question.tags,
+            Some(vec!["rust".to_string(), "c++".to_string()])
+        );
+        assert!(question.validate().is_ok());
+
+        let question = serde_json::from_value::<NewQuestion>(serde_json::json!({
+            "title": " ",
+            "content": "x".repeat(10001),
+        }))
+        .unwrap();
+        assert_eq!(question.tags, None);
+        assert_eq!(
+            field_errors(&question.validate().unwrap_err()),
+            vec![
+                FieldError::new("content", "must not be longer than 10000 characters"),
+                FieldError::new("title", "must not be empty"),
+            ]
+        );
+
+        let question = serde_json::from_value::<NewQuestion>(serde_json::json!({
+            "title": "Title",
+            "content": "Content",
+            "tags": ["two words"],
+        }))
+        .unwrap();
+        assert_eq!(
+            field_errors(&question.validate().unwrap_err()),
+            vec![FieldError::new(
+                "tags",
+                "\"two words\" may only contain letters, digits, '-', '.', '+' and '#'"
+            )]
+        );
+    }
+
+    #[test]
+    fn normalizes_and_validates_profiles() {
+        let profile = serde_json::from_value::<ProfileUpdate>(serde_json::json!({
+            "display_name": "  Bob ",
+            "bio": " ",
+            "website": "https://example.com/bob",
+        }))
+        .unwrap();
+        assert_eq!(profile.display_name.as_deref(), Some("Bob"));
+        assert_eq!(profile.bio, None);
+        assert!(profile.validate().is_ok());
+
+        let profile = serde_json::from_value::<ProfileUpdate>(serde_json::json!({
+            "display_name": "x".repeat(65),
+            "website": "javascript:alert(1)",
+        }))
+        .unwrap();
+        assert_eq!(
+            field_errors(&profile.validate().unwrap_err()),
+            vec![
+                FieldError::new("display_name", "must not be longer than 64 characters"),
+                FieldError::new("website", "must be an http or https URL"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_new_tokens() {
+        let token = serde_json::from_value::<NewToken>(serde_json::json!({
+            "name": " deploy bot ",
+            "scopes": ["answers:write"],
+            "expires_at": Utc::now() + chrono::Duration::days(30),
+        }))
+        .unwrap();
+        assert_eq!(token.name, "deploy bot");
+        assert!(token.validate().is_ok());
+
+        let token = serde_json::from_value::<NewToken>(serde_json::json!({
+            "name": "",
+            "scopes": [],
+            "expires_at": Utc::now() - chrono::Duration::days(1),
+        }))
+        .unwrap();
+        assert_eq!(
+            field_errors(&token.validate().unwrap_err()),
+            vec![
+                FieldError::new("expires_at", "must be in the future"),
+                FieldError::new("name", "must not be empty"),
+                FieldError::new("scopes", "must contain at least one scope"),
+            ]
+        );
+    }
+}